@@ -0,0 +1,4 @@
+-- Allergen flags (the 14 EU allergens) and optional nutrition/ingredient declarations
+ALTER TABLE Product ADD COLUMN allergens TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE Product ADD COLUMN nutrition TEXT; -- JSON, values per 100 g
+ALTER TABLE Product ADD COLUMN ingredients TEXT;
@@ -0,0 +1,5 @@
+ALTER TABLE Product ADD COLUMN release_at INTEGER; -- Hidden from the store until this UNIX timestamp
+ALTER TABLE Product ADD COLUMN availability TEXT; -- JSON, recurring weekday/time of day window
+ALTER TABLE Product ADD COLUMN new_since INTEGER; -- Start of the automatic new_product expiry
+
+UPDATE Product SET new_since = strftime('%s', 'now') WHERE json_extract(flags, '$.new_product') = 1;
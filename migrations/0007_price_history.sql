@@ -0,0 +1,15 @@
+-- Price list, the effective price of a product is the latest entry with effective_from <= now
+CREATE TABLE ProductPrice (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product INTEGER NOT NULL,
+    price REAL NOT NULL,
+    effective_from INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY("product") REFERENCES Product("id") ON DELETE CASCADE
+);
+
+CREATE INDEX ProductPriceIndex ON ProductPrice (product, effective_from);
+
+-- Start the history with the current prices
+INSERT INTO ProductPrice (product, price, effective_from, created_at)
+SELECT id, price, strftime('%s', 'now'), strftime('%s', 'now') FROM Product;
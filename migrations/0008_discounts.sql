@@ -0,0 +1,16 @@
+ALTER TABLE Product ADD COLUMN category TEXT;
+
+CREATE TABLE DiscountRule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    target TEXT NOT NULL, -- JSON, all products, a product or a category
+    discount TEXT NOT NULL, -- JSON, percentage or fixed amount per unit
+    starts_at INTEGER, -- Campaign period, UNIX timestamps
+    ends_at INTEGER,
+    time_window TEXT, -- JSON, recurring weekday/time of day window
+    active INTEGER NOT NULL DEFAULT 1 CHECK(active IN (0, 1))
+);
+
+-- Discount per unit, the paid unit price is price - discount
+ALTER TABLE TransactionItem ADD COLUMN discount REAL NOT NULL DEFAULT 0;
+ALTER TABLE TransactionItem ADD COLUMN discount_rule INTEGER REFERENCES DiscountRule(id) ON DELETE SET NULL;
@@ -0,0 +1,19 @@
+CREATE TABLE Bundle (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    price REAL NOT NULL,
+    auto_apply INTEGER NOT NULL DEFAULT 0 CHECK(auto_apply IN (0, 1)), -- Apply to qualifying carts at checkout
+    active INTEGER NOT NULL DEFAULT 1 CHECK(active IN (0, 1))
+);
+
+CREATE TABLE BundleComponent (
+    bundle INTEGER NOT NULL,
+    product INTEGER NOT NULL,
+    quantity INTEGER NOT NULL CHECK(quantity > 0),
+    UNIQUE(bundle, product),
+    FOREIGN KEY("bundle") REFERENCES Bundle("id") ON DELETE CASCADE,
+    FOREIGN KEY("product") REFERENCES Product("id") ON DELETE CASCADE
+);
+
+-- Bundles are sold as their components, with the bundle saving spread over them as discount
+ALTER TABLE TransactionItem ADD COLUMN bundle INTEGER REFERENCES Bundle(id) ON DELETE SET NULL;
@@ -0,0 +1,14 @@
+-- Existing users keep paying the member price until an admin says otherwise
+ALTER TABLE User ADD COLUMN is_member INTEGER NOT NULL DEFAULT 1 CHECK(is_member IN (0, 1));
+
+-- Overrides the product price for users in a tier
+CREATE TABLE ProductPriceTier (
+    product INTEGER NOT NULL,
+    tier TEXT NOT NULL CHECK(tier IN ('member', 'non_member', 'bot', 'maintainer', 'admin')),
+    price REAL NOT NULL,
+    UNIQUE(product, tier) ON CONFLICT REPLACE,
+    FOREIGN KEY("product") REFERENCES Product("id") ON DELETE CASCADE
+);
+
+ALTER TABLE StoreTransaction ADD COLUMN price_tier TEXT; -- The buyer's tier at checkout
+ALTER TABLE TransactionItem ADD COLUMN price_tier TEXT; -- NULL if the product's base price was charged
@@ -0,0 +1,26 @@
+CREATE TABLE StampCardProgram (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    target TEXT NOT NULL, -- JSON, all products, a product or a category
+    stamps_required INTEGER NOT NULL CHECK(stamps_required > 0), -- Stamps needed for one free unit
+    active INTEGER NOT NULL DEFAULT 1 CHECK(active IN (0, 1))
+);
+
+-- Stamp ledger, a user's stamps on a card is the sum of their entries.
+-- Entries follow their transaction, so undoing a purchase also undoes its stamps
+CREATE TABLE StampEntry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER NOT NULL,
+    program INTEGER NOT NULL,
+    transaction_id INTEGER,
+    stamps INTEGER NOT NULL, -- Positive when earned, negative when redeemed
+    datetime INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE,
+    FOREIGN KEY("program") REFERENCES StampCardProgram("id") ON DELETE CASCADE,
+    FOREIGN KEY("transaction_id") REFERENCES StoreTransaction("id") ON DELETE CASCADE
+);
+
+CREATE INDEX StampEntryIndex ON StampEntry (user, program);
+
+-- Set on the free unit of a redeemed stamp card
+ALTER TABLE TransactionItem ADD COLUMN stamp_program INTEGER REFERENCES StampCardProgram(id) ON DELETE SET NULL;
@@ -0,0 +1,21 @@
+-- Ledger entry type, older entries are classified from amount and admin_issued
+ALTER TABLE StoreTransaction ADD COLUMN kind TEXT NOT NULL DEFAULT 'purchase';
+UPDATE StoreTransaction SET kind = CASE
+    WHEN admin_issued = 1 THEN 'adjustment'
+    WHEN amount > 0 THEN 'deposit'
+    ELSE 'purchase'
+END;
+ALTER TABLE StoreTransaction ADD COLUMN payment_reference TEXT; -- E.g. Swish payment id, shared by a deposit and its bonus
+ALTER TABLE StoreTransaction ADD COLUMN note TEXT; -- Label shown in the ledger, e.g. the bonus campaign
+
+CREATE INDEX StoreTransactionPaymentReference ON StoreTransaction(payment_reference);
+
+CREATE TABLE DepositBonusRule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    bonus TEXT NOT NULL, -- JSON, percentage of the deposit or fixed amount
+    min_amount REAL NOT NULL DEFAULT 0, -- Smallest deposit that gets the bonus
+    starts_at INTEGER, -- Campaign period, UNIX timestamps
+    ends_at INTEGER,
+    active INTEGER NOT NULL DEFAULT 1 CHECK(active IN (0, 1))
+);
@@ -0,0 +1,24 @@
+CREATE TABLE Voucher (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    code TEXT UNIQUE NOT NULL, -- Upper case
+    value REAL NOT NULL, -- Kronor credited per redemption
+    max_redemptions INTEGER NOT NULL DEFAULT 1, -- 1 for single-use codes
+    expires_at INTEGER, -- UNIX timestamp
+    note TEXT, -- E.g. which raffle the code was made for
+    created_by INTEGER,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY("created_by") REFERENCES User("id") ON DELETE SET NULL
+);
+
+-- A user can redeem each code once
+CREATE TABLE VoucherRedemption (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    voucher INTEGER NOT NULL,
+    user INTEGER,
+    transaction_id INTEGER,
+    redeemed_at INTEGER NOT NULL,
+    UNIQUE(voucher, user),
+    FOREIGN KEY("voucher") REFERENCES Voucher("id") ON DELETE CASCADE,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE SET NULL,
+    FOREIGN KEY("transaction_id") REFERENCES StoreTransaction("id") ON DELETE SET NULL
+);
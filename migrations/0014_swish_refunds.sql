@@ -0,0 +1,17 @@
+ALTER TABLE SwishPaymentRequest ADD COLUMN payment_reference TEXT; -- Swish's reference, set when paid
+
+CREATE TABLE SwishRefund (
+    id TEXT UNIQUE NOT NULL, -- Our instruction UUID, also used by Swish
+    payment_request TEXT NOT NULL,
+    user INTEGER,
+    amount REAL NOT NULL,
+    bonus_reversed REAL NOT NULL DEFAULT 0, -- Deposit bonus taken back together with the refund
+    status TEXT NOT NULL CHECK(status IN ('created', 'validated', 'debited', 'paid', 'error')),
+    callback_identifier TEXT NOT NULL,
+    location TEXT,
+    issued_by INTEGER,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY("payment_request") REFERENCES SwishPaymentRequest("id") ON DELETE CASCADE,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE SET NULL,
+    FOREIGN KEY("issued_by") REFERENCES User("id") ON DELETE SET NULL
+);
@@ -0,0 +1,4 @@
+-- Requests made before this are treated as stale right away
+ALTER TABLE SwishPaymentRequest ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX SwishPaymentRequestStatus ON SwishPaymentRequest(status, created_at);
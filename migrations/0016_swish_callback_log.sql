@@ -0,0 +1,12 @@
+-- Every callback received from (or claiming to be from) Swish, kept for auditing
+CREATE TABLE SwishCallbackLog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL CHECK(kind IN ('payment', 'refund')),
+    reference TEXT, -- Payment request or refund id, if the payload could be parsed
+    source_ip TEXT,
+    payload TEXT NOT NULL, -- Raw request body
+    rejection TEXT, -- Why nothing was done, NULL if the callback was accepted
+    received_at INTEGER NOT NULL
+);
+
+CREATE INDEX SwishCallbackLogReceivedAt ON SwishCallbackLog(received_at);
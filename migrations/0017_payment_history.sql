@@ -0,0 +1,14 @@
+ALTER TABLE SwishPaymentRequest ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0; -- Last status change
+ALTER TABLE SwishPaymentRequest ADD COLUMN paid_at INTEGER;
+ALTER TABLE SwishPaymentRequest ADD COLUMN error_code TEXT; -- As reported by Swish for failed payments
+ALTER TABLE SwishPaymentRequest ADD COLUMN error_message TEXT;
+
+-- Paid requests were credited with a deposit referencing the request
+UPDATE SwishPaymentRequest SET paid_at = (
+    SELECT MIN(st.datetime) FROM StoreTransaction st
+    WHERE st.payment_reference = SwishPaymentRequest.id AND st.kind = 'deposit'
+) WHERE status = 'paid';
+
+UPDATE SwishPaymentRequest SET updated_at = COALESCE(paid_at, created_at);
+
+CREATE INDEX SwishPaymentRequestUser ON SwishPaymentRequest(user, created_at);
@@ -0,0 +1,18 @@
+-- How money entered or left the store on deposit and refund entries, NULL for everything else
+ALTER TABLE StoreTransaction ADD COLUMN payment_method TEXT CHECK(payment_method IN ('swish', 'cash'));
+
+UPDATE StoreTransaction SET payment_method = 'swish'
+WHERE kind IN ('deposit', 'refund') AND payment_reference IN (SELECT id FROM SwishPaymentRequest);
+
+CREATE TABLE CashDeposit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER,
+    amount REAL NOT NULL,
+    refunded REAL NOT NULL DEFAULT 0, -- Cash handed back
+    received_by INTEGER, -- The maintainer who took the cash
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE SET NULL,
+    FOREIGN KEY("received_by") REFERENCES User("id") ON DELETE SET NULL
+);
+
+CREATE INDEX CashDepositCreatedAt ON CashDeposit(created_at);
@@ -0,0 +1,26 @@
+-- Cash taken out of the box, e.g. to buy restock
+CREATE TABLE CashWithdrawal (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    amount REAL NOT NULL CHECK(amount > 0),
+    note TEXT NOT NULL, -- What the cash was used for
+    created_by INTEGER,
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY("created_by") REFERENCES User("id") ON DELETE SET NULL
+);
+
+-- A counting session, the counted amount is the baseline for the next expected amount
+CREATE TABLE CashCount (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    counted REAL NOT NULL,
+    expected REAL NOT NULL,
+    note TEXT,
+    counted_by INTEGER,
+    created_at INTEGER NOT NULL,
+    -- Latest ledger entry and withdrawal at the time, later ones are what moved since the count
+    transaction_id INTEGER NOT NULL,
+    withdrawal_id INTEGER NOT NULL,
+    FOREIGN KEY("counted_by") REFERENCES User("id") ON DELETE SET NULL
+);
+
+CREATE INDEX CashWithdrawalCreatedAt ON CashWithdrawal(created_at);
+CREATE INDEX CashCountCreatedAt ON CashCount(created_at);
@@ -0,0 +1,20 @@
+-- A user's balance paid out, e.g. when leaving. The amount is held from the balance when requested
+CREATE TABLE Payout (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER,
+    amount REAL NOT NULL CHECK(amount > 0),
+    method TEXT NOT NULL CHECK(method IN ('swish', 'cash')),
+    destination TEXT, -- Phone number to Swish the money to
+    status TEXT NOT NULL CHECK(status IN ('requested', 'approved', 'paid', 'rejected', 'cancelled')),
+    note TEXT, -- E.g. why it was rejected
+    requested_at INTEGER NOT NULL,
+    approved_by INTEGER,
+    approved_at INTEGER,
+    paid_by INTEGER,
+    paid_at INTEGER,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE SET NULL,
+    FOREIGN KEY("approved_by") REFERENCES User("id") ON DELETE SET NULL,
+    FOREIGN KEY("paid_by") REFERENCES User("id") ON DELETE SET NULL
+);
+
+CREATE INDEX PayoutStatus ON Payout(status, requested_at);
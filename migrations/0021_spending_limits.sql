@@ -0,0 +1,26 @@
+-- Spending caps a user sets on themselves, NULL means no cap.
+-- Loosening a cap waits out the cooldown as a pending change, tightening applies right away
+CREATE TABLE SpendingLimit (
+    user INTEGER PRIMARY KEY,
+    daily REAL CHECK(daily > 0),
+    weekly REAL CHECK(weekly > 0),
+    cooldown_days INTEGER NOT NULL DEFAULT 0 CHECK(cooldown_days >= 0),
+    pending_daily REAL CHECK(pending_daily > 0),
+    pending_weekly REAL CHECK(pending_weekly > 0),
+    pending_cooldown_days INTEGER CHECK(pending_cooldown_days >= 0),
+    pending_at INTEGER, -- When the pending change applies, NULL if there is none
+    updated_at INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE
+);
+
+-- What users with a spending limit spent on purchases, kept apart from StoreTransaction since private
+-- purchases are not linked to the user. Only the last 8 days are kept, enough for the weekly cap
+CREATE TABLE SpendingEntry (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER NOT NULL,
+    amount REAL NOT NULL,
+    datetime INTEGER NOT NULL,
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE
+);
+
+CREATE INDEX SpendingEntryIndex ON SpendingEntry (user, datetime);
@@ -0,0 +1,14 @@
+-- Email notification preferences, all opt-in
+ALTER TABLE User ADD COLUMN low_balance_alerts INTEGER NOT NULL DEFAULT 0 CHECK(low_balance_alerts IN (0, 1));
+ALTER TABLE User ADD COLUMN low_balance_threshold REAL NOT NULL DEFAULT 20 CHECK(low_balance_threshold >= 0);
+ALTER TABLE User ADD COLUMN email_receipts INTEGER NOT NULL DEFAULT 0 CHECK(email_receipts IN (0, 1));
+ALTER TABLE User ADD COLUMN monthly_statements INTEGER NOT NULL DEFAULT 0 CHECK(monthly_statements IN (0, 1));
+
+-- Months a statement has been sent for, so restarts do not send it twice
+CREATE TABLE MonthlyStatement (
+    user INTEGER NOT NULL,
+    month TEXT NOT NULL, -- YYYY-MM
+    sent_at INTEGER NOT NULL,
+    PRIMARY KEY (user, month),
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE
+);
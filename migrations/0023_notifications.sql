@@ -0,0 +1,14 @@
+-- In-app notification inbox
+CREATE TABLE Notification (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER NOT NULL,
+    kind TEXT NOT NULL, -- See NotificationKind
+    message TEXT NOT NULL,
+    transaction_id INTEGER, -- The deposit or adjustment notified about
+    created_at INTEGER NOT NULL,
+    read_at INTEGER, -- NULL while unread
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE,
+    FOREIGN KEY("transaction_id") REFERENCES StoreTransaction("id") ON DELETE SET NULL
+);
+
+CREATE INDEX NotificationUser ON Notification (user, read_at);
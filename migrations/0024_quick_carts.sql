@@ -0,0 +1,25 @@
+-- Products a user wants to hear about when restocked
+CREATE TABLE FavouriteProduct (
+    user INTEGER NOT NULL,
+    product INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (user, product),
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE,
+    FOREIGN KEY("product") REFERENCES Product("id") ON DELETE CASCADE
+);
+
+CREATE INDEX FavouriteProductProduct ON FavouriteProduct (product);
+
+ALTER TABLE Notification ADD COLUMN product INTEGER REFERENCES Product("id") ON DELETE SET NULL; -- The restocked product
+
+-- Named carts a user buys often, e.g. "morning coffee"
+CREATE TABLE QuickCart (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    cart TEXT NOT NULL, -- JSON, same shape as the body of /api/buy_products
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    UNIQUE(user, name),
+    FOREIGN KEY("user") REFERENCES User("id") ON DELETE CASCADE
+);
@@ -54,20 +54,20 @@ pub async fn get_user_from_cookie(pool: &SqlitePool, cookie: Option<Cookie<'stat
         );
     };
 
-    let session = get_session(pool, token.id).await.map_err(|err| DatabaseError::from(err))?;
+    let session = get_session(pool, token.id).await?;
 
     match session {
         Some(session) => {
             let id = session.user;
             let user = crud::get_user(pool, Some(id), None).await?;
-            return Ok(user);
+            Ok(user)
         },
         None => {
-            return Err(
+            Err(
                 AuthError::new("Session not found or expired")
                     .with_status(StatusCode::UNAUTHORIZED)
                     .into()
-            );
+            )
         }
     }
 }
@@ -92,9 +92,9 @@ pub async fn create_session(pool: &SqlitePool, user_id: u32) -> Result<(Session,
     sqlx::query("
         INSERT INTO Session (id, secret_hash, created_at, user)
         VALUES (?, ?, ?, ?)").bind(id).bind(secret_hash).bind(now).bind(user_id)
-        .execute(pool).await.map_err(|err| DatabaseError::from(err))?;
+        .execute(pool).await.map_err(DatabaseError::from)?;
     
-    return Ok((session, token));
+    Ok((session, token))
 }
 
 pub async fn validate_session(pool: &SqlitePool, token: Token) -> Result<Option<Session>, DatabaseError> {
@@ -102,10 +102,9 @@ pub async fn validate_session(pool: &SqlitePool, token: Token) -> Result<Option<
 
     if let Some(session) = session {
         let token_secret_hash = Sha256::digest(token.secret).to_vec();
-        if let Ok(db_secret_hash) = hex::decode(session.secret_hash.clone()) {
-            if eq_hashes(token_secret_hash, db_secret_hash) {
-                return Ok(Some(session));
-            }
+        if let Ok(db_secret_hash) = hex::decode(session.secret_hash.clone())
+            && eq_hashes(token_secret_hash, db_secret_hash) {
+            return Ok(Some(session));
         }
     }
 
@@ -132,10 +131,10 @@ async fn get_session(pool: &SqlitePool, session_id: String) -> Result<Option<Ses
     };
 
     if now - session.created_at < Duration::days(7).whole_seconds() {
-        return Ok(Some(session));
+        Ok(Some(session))
     } else {
         delete_session(pool, session_id).await?;
-        return Ok(None);
+        Ok(None)
     }
 }
 
@@ -152,7 +151,7 @@ fn gen_secure_random_str() -> Option<String> {
         let i = (rand >> 3) as usize;
         result.push(READABLE_ALPHABET[i] as char);
     }
-    return Some(result);
+    Some(result)
 }
 
 fn eq_hashes(hash1: Vec<u8>, hash2: Vec<u8>) -> bool {
@@ -164,5 +163,5 @@ fn eq_hashes(hash1: Vec<u8>, hash2: Vec<u8>) -> bool {
             return false;
         }
     }
-    return true;
+    true
 }
@@ -0,0 +1,406 @@
+//! Local stand-in for the Swish APIs, run with `SWISH_ENVIRONMENT=simulator` so the whole
+//! payment flow works offline. Payment requests and refunds are resolved after `--delay-ms`
+//! with the configured outcome and reported to their callback URL like Swish would.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use actix_web::{App, HttpResponse, HttpServer, get, patch, post, put, web::{self, Data, Json}};
+use clap::{Parser, ValueEnum};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+
+#[derive(Parser)]
+#[command(about = "Local Swish API simulator")]
+struct Args {
+    #[arg(long, default_value_t = 8081)]
+    port: u16,
+    /// How payment requests end, can be changed while running through /simulator/outcome
+    #[arg(long, value_enum, default_value_t = Outcome::Paid)]
+    outcome: Outcome,
+    /// How refunds end
+    #[arg(long, value_enum, default_value_t = RefundOutcome::Paid)]
+    refund_outcome: RefundOutcome,
+    /// Time from creation until the outcome is applied
+    #[arg(long, default_value_t = 2000)]
+    delay_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Paid,
+    Declined,
+    Error,
+    Cancelled,
+    /// Stays pending until resolved through /simulator/payments/{id}/{outcome}
+    Pending,
+    /// Paid, but the callback is never sent
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RefundOutcome {
+    Paid,
+    Error,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PaymentRequest {
+    id: String,
+    payee_payment_reference: Option<String>,
+    payment_reference: Option<String>,
+    callback_url: String,
+    payer_alias: Option<String>,
+    payee_alias: String,
+    amount: f64,
+    currency: String,
+    message: String,
+    status: String,
+    date_created: String,
+    date_paid: Option<String>,
+    error_code: Option<String>,
+    error_message: Option<String>,
+    #[serde(skip)]
+    callback_identifier: Option<String>,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Refund {
+    id: String,
+    payment_reference: Option<String>,
+    original_payment_reference: String,
+    callback_url: String,
+    payer_alias: String,
+    payee_alias: Option<String>,
+    amount: f64,
+    currency: String,
+    message: String,
+    status: String,
+    date_created: String,
+    date_paid: Option<String>,
+    error_code: Option<String>,
+    error_message: Option<String>,
+    #[serde(skip)]
+    callback_identifier: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PaymentRequestBody {
+    payee_alias: String,
+    amount: f64,
+    currency: String,
+    callback_url: String,
+    message: Option<String>,
+    callback_identifier: Option<String>,
+    payee_payment_reference: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RefundBody {
+    original_payment_reference: String,
+    callback_url: String,
+    payer_alias: String,
+    amount: f64,
+    currency: String,
+    message: Option<String>,
+    callback_identifier: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SwishError {
+    error_code: &'static str,
+    error_message: &'static str,
+    additional_information: String,
+}
+
+struct Simulator {
+    base_url: String,
+    outcome: Mutex<Outcome>,
+    refund_outcome: Mutex<RefundOutcome>,
+    delay: Duration,
+    payments: Mutex<HashMap<String, PaymentRequest>>,
+    refunds: Mutex<HashMap<String, Refund>>,
+    client: reqwest::Client,
+}
+
+fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+fn swish_error(status: actix_web::http::StatusCode, code: &'static str, message: &'static str) -> HttpResponse {
+    HttpResponse::build(status).json([SwishError { error_code: code, error_message: message, additional_information: String::new() }])
+}
+
+/// Sends `body` to `url` the way Swish does, with the callback identifier as a header
+async fn send_callback<T: serde::Serialize>(client: &reqwest::Client, url: &str, callback_identifier: Option<&str>, body: &T) {
+    let mut request = client.post(url).json(body);
+    if let Some(identifier) = callback_identifier {
+        request = request.header("callbackIdentifier", identifier);
+    }
+    match request.send().await {
+        Ok(response) => log::info!("Callback to {url} answered {}", response.status()),
+        Err(err) => log::warn!("Callback to {url} failed: {err}"),
+    }
+}
+
+/// Applies `outcome` to a pending payment and fires its callback. Returns false if the payment is not pending
+async fn resolve_payment(simulator: &Simulator, id: &str, outcome: Outcome) -> bool {
+    let payment = {
+        let mut payments = simulator.payments.lock().unwrap();
+        let Some(payment) = payments.get_mut(id) else { return false };
+        if payment.status != "CREATED" {
+            return false;
+        }
+        match outcome {
+            Outcome::Paid | Outcome::Lost => {
+                payment.status = String::from("PAID");
+                payment.payment_reference = Some(Uuid::new_v4().simple().to_string().to_uppercase());
+                payment.payer_alias = Some(String::from("46700000000"));
+                payment.date_paid = Some(now());
+            }
+            Outcome::Declined => payment.status = String::from("DECLINED"),
+            Outcome::Error => {
+                payment.status = String::from("ERROR");
+                payment.error_code = Some(String::from("TM01"));
+                payment.error_message = Some(String::from("Swish timed out before the payment was started"));
+            }
+            Outcome::Cancelled => payment.status = String::from("CANCELLED"),
+            Outcome::Pending => return true,
+        }
+        payment.clone()
+    };
+    log::info!("Payment {id} is now {}", payment.status);
+    if outcome != Outcome::Lost {
+        send_callback(&simulator.client, &payment.callback_url, payment.callback_identifier.as_deref(), &payment).await;
+    }
+    true
+}
+
+#[put("/swish-cpcapi/api/v2/paymentrequests/{id}")]
+async fn create_payment_request(simulator: Data<Simulator>, path: web::Path<String>, body: Json<PaymentRequestBody>) -> HttpResponse {
+    let id = path.into_inner();
+    if body.currency != "SEK" {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "AM03", "Invalid or missing Currency");
+    }
+    if !(1.0..=999_999_999_999.99).contains(&body.amount) {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "AM06", "Specified transaction amount is less than agreed minimum");
+    }
+    let mut payments = simulator.payments.lock().unwrap();
+    if payments.contains_key(&id) {
+        return swish_error(actix_web::http::StatusCode::CONFLICT, "RP08", "The payment request has already been created");
+    }
+
+    let body = body.into_inner();
+    let token = Uuid::new_v4().simple().to_string();
+    payments.insert(id.clone(), PaymentRequest {
+        id: id.clone(),
+        payee_payment_reference: body.payee_payment_reference,
+        payment_reference: None,
+        callback_url: body.callback_url,
+        payer_alias: None,
+        payee_alias: body.payee_alias,
+        amount: body.amount,
+        currency: body.currency,
+        message: body.message.unwrap_or_default(),
+        status: String::from("CREATED"),
+        date_created: now(),
+        date_paid: None,
+        error_code: None,
+        error_message: None,
+        callback_identifier: body.callback_identifier,
+    });
+    drop(payments);
+
+    let outcome = *simulator.outcome.lock().unwrap();
+    log::info!("Created payment request {id} for {} SEK, resolving as {outcome:?}", body.amount);
+    let background = simulator.clone();
+    let payment_id = id.clone();
+    actix_web::rt::spawn(async move {
+        actix_web::rt::time::sleep(background.delay).await;
+        resolve_payment(&background, &payment_id, outcome).await;
+    });
+
+    HttpResponse::Created()
+        .insert_header(("Location", format!("{}/swish-cpcapi/api/v1/paymentrequests/{id}", simulator.base_url)))
+        .insert_header(("PaymentRequestToken", token))
+        .finish()
+}
+
+#[get("/swish-cpcapi/api/v1/paymentrequests/{id}")]
+async fn get_payment_request(simulator: Data<Simulator>, path: web::Path<String>) -> HttpResponse {
+    match simulator.payments.lock().unwrap().get(&path.into_inner()) {
+        Some(payment) => HttpResponse::Ok().json(payment),
+        None => swish_error(actix_web::http::StatusCode::NOT_FOUND, "RP04", "No payment request found"),
+    }
+}
+
+/// Swish only lets a merchant cancel requests that have not been answered yet
+#[patch("/swish-cpcapi/api/v1/paymentrequests/{id}")]
+async fn cancel_payment_request(simulator: Data<Simulator>, path: web::Path<String>) -> HttpResponse {
+    let mut payments = simulator.payments.lock().unwrap();
+    let Some(payment) = payments.get_mut(&path.into_inner()) else {
+        return swish_error(actix_web::http::StatusCode::NOT_FOUND, "RP04", "No payment request found");
+    };
+    if payment.status != "CREATED" {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "RP07", "Transaction status is not CREATED");
+    }
+    payment.status = String::from("CANCELLED");
+    log::info!("Payment {} was cancelled", payment.id);
+    HttpResponse::Ok().json(payment.clone())
+}
+
+#[put("/swish-cpcapi/api/v2/refunds/{id}")]
+async fn create_refund(simulator: Data<Simulator>, path: web::Path<String>, body: Json<RefundBody>) -> HttpResponse {
+    let id = path.into_inner();
+    let payments = simulator.payments.lock().unwrap();
+    let Some(original) = payments.values()
+        .find(|p| p.payment_reference.as_deref() == Some(body.original_payment_reference.as_str())) else {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "RF02", "Original Payment not found or original payment is more than 13 months old");
+    };
+    if original.payee_alias != body.payer_alias {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "RF03", "Payer alias in the refund does not match the payee alias in the original payment");
+    }
+    let mut refunds = simulator.refunds.lock().unwrap();
+    if refunds.contains_key(&id) {
+        return swish_error(actix_web::http::StatusCode::CONFLICT, "RF09", "Refund already in progress");
+    }
+    let refunded: f64 = refunds.values()
+        .filter(|r| r.original_payment_reference == body.original_payment_reference && r.status != "ERROR")
+        .map(|r| r.amount)
+        .sum();
+    if body.amount <= 0.0 || body.amount > original.amount - refunded + 0.001 {
+        return swish_error(actix_web::http::StatusCode::UNPROCESSABLE_ENTITY, "RF08", "Amount value is too large, or amount exceeds the amount of the original payment minus any previous refunds");
+    }
+
+    let body = body.into_inner();
+    refunds.insert(id.clone(), Refund {
+        id: id.clone(),
+        payment_reference: None,
+        original_payment_reference: body.original_payment_reference,
+        callback_url: body.callback_url,
+        payer_alias: body.payer_alias,
+        payee_alias: original.payer_alias.clone(),
+        amount: body.amount,
+        currency: body.currency,
+        message: body.message.unwrap_or_default(),
+        status: String::from("CREATED"),
+        date_created: now(),
+        date_paid: None,
+        error_code: None,
+        error_message: None,
+        callback_identifier: body.callback_identifier,
+    });
+    drop(refunds);
+    drop(payments);
+
+    let outcome = *simulator.refund_outcome.lock().unwrap();
+    log::info!("Created refund {id} for {} SEK, resolving as {outcome:?}", body.amount);
+    let background = simulator.clone();
+    let refund_id = id.clone();
+    actix_web::rt::spawn(async move {
+        actix_web::rt::time::sleep(background.delay).await;
+        let refund = {
+            let mut refunds = background.refunds.lock().unwrap();
+            let Some(refund) = refunds.get_mut(&refund_id) else { return };
+            match outcome {
+                RefundOutcome::Paid => {
+                    refund.status = String::from("PAID");
+                    refund.payment_reference = Some(Uuid::new_v4().simple().to_string().to_uppercase());
+                    refund.date_paid = Some(now());
+                }
+                RefundOutcome::Error => {
+                    refund.status = String::from("ERROR");
+                    refund.error_code = Some(String::from("RF07"));
+                    refund.error_message = Some(String::from("Transaction declined"));
+                }
+            }
+            refund.clone()
+        };
+        send_callback(&background.client, &refund.callback_url, refund.callback_identifier.as_deref(), &refund).await;
+    });
+
+    HttpResponse::Created()
+        .insert_header(("Location", format!("{}/swish-cpcapi/api/v1/refunds/{id}", simulator.base_url)))
+        .finish()
+}
+
+#[get("/swish-cpcapi/api/v1/refunds/{id}")]
+async fn get_refund(simulator: Data<Simulator>, path: web::Path<String>) -> HttpResponse {
+    match simulator.refunds.lock().unwrap().get(&path.into_inner()) {
+        Some(refund) => HttpResponse::Ok().json(refund),
+        None => swish_error(actix_web::http::StatusCode::NOT_FOUND, "RF04", "No refund found"),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct OutcomeBody {
+    outcome: Option<Outcome>,
+    refund_outcome: Option<RefundOutcome>,
+}
+
+/// Changes the outcome of requests created from now on
+#[post("/simulator/outcome")]
+async fn set_outcome(simulator: Data<Simulator>, body: Json<OutcomeBody>) -> HttpResponse {
+    if let Some(outcome) = body.outcome {
+        *simulator.outcome.lock().unwrap() = outcome;
+    }
+    if let Some(refund_outcome) = body.refund_outcome {
+        *simulator.refund_outcome.lock().unwrap() = refund_outcome;
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Resolves a pending payment request right away
+#[post("/simulator/payments/{id}/{outcome}")]
+async fn force_outcome(simulator: Data<Simulator>, path: web::Path<(String, Outcome)>) -> HttpResponse {
+    let (id, outcome) = path.into_inner();
+    match resolve_payment(&simulator, &id, outcome).await {
+        true => HttpResponse::Ok().finish(),
+        false => HttpResponse::Conflict().body("Payment request not found or not pending"),
+    }
+}
+
+#[get("/simulator/payments")]
+async fn list_payments(simulator: Data<Simulator>) -> HttpResponse {
+    let payments: Vec<PaymentRequest> = simulator.payments.lock().unwrap().values().cloned().collect();
+    HttpResponse::Ok().json(payments)
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_default_env().filter_level(log::LevelFilter::Info).init();
+
+    let simulator = Data::new(Simulator {
+        base_url: format!("http://127.0.0.1:{}", args.port),
+        outcome: Mutex::new(args.outcome),
+        refund_outcome: Mutex::new(args.refund_outcome),
+        delay: Duration::from_millis(args.delay_ms),
+        payments: Mutex::new(HashMap::new()),
+        refunds: Mutex::new(HashMap::new()),
+        client: reqwest::Client::new(),
+    });
+    log::info!("Swish simulator listening on {}", simulator.base_url);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(simulator.clone())
+            .service(create_payment_request)
+            .service(get_payment_request)
+            .service(cancel_payment_request)
+            .service(create_refund)
+            .service(get_refund)
+            .service(set_outcome)
+            .service(force_outcome)
+            .service(list_payments)
+    })
+    .bind(("127.0.0.1", args.port))?
+    .run()
+    .await
+}
@@ -1,11 +1,11 @@
 use sqlx::{QueryBuilder, Result, SqlitePool};
 use time::{OffsetDateTime, UtcDateTime};
 
-use crate::database::model::{SwishPaymentRequestRow, TransactionItemRow, TransactionRow};
+use crate::database::model::{BundleComponentRow, BundleRow, CashCountRow, CashDepositRow, CashFlowRow, CashWithdrawalRow, NotificationRow, PaymentMethodTotalRow, PayoutRow, QuickCartRow, SalesRow, DepositBonusRuleRow, DiscountRuleRow, ProductPriceRow, SettlementPaymentRow, SpendingLimitRow, ProductPriceTierRow, StampCardProgramRow, SwishCallbackLogRow, SwishPaymentRequestRow, SwishPaymentSummaryRow, SwishRefundRow, TransactionItemRow, TransactionRow, VoucherRedemptionRow, VoucherRow};
 use crate::error::DatabaseError;
-use crate::model::{PendingTransaction, TransactionDetail, TransactionQuery, TransactionSummary};
+use crate::model::{Bundle, NotificationKind, PaymentRequestQuery, PayoutStatus, PendingTransaction, TransactionKind, TierPrice, TransactionDetail, TransactionQuery, TransactionSummary};
 use crate::Role;
-use crate::routes::payment::swish;
+use crate::routes::{payment::{PaymentMethod, swish}, products::Cart};
 
 use super::model::UserRow;
 use super::model::ProductRow;
@@ -24,7 +24,7 @@ pub async fn create_user(pool: &SqlitePool, name: Option<&str>, email: &str, goo
         INSERT INTO User (name, email, google_id, role, balance, on_leaderboard, private_transactions)
         VALUES (?, ?, ?, ?, 0, 0, 0)
         RETURNING id
-        "#).bind(name).bind(email).bind(google_id).bind(&role).fetch_one(pool)
+        "#).bind(name).bind(email).bind(google_id).bind(role).fetch_one(pool)
     .await?;
 
     Ok(UserRow { 
@@ -35,14 +35,20 @@ pub async fn create_user(pool: &SqlitePool, name: Option<&str>, email: &str, goo
         role,
         balance: 0.0,
         on_leaderboard: true,
-        private_transactions: false
+        private_transactions: false,
+        is_member: true,
+        low_balance_alerts: false,
+        low_balance_threshold: 20.0,
+        email_receipts: false,
+        monthly_statements: false,
     })
 }
 
 pub async fn get_user(pool: &SqlitePool, user_id: Option<u32>, google_id: Option<&str>) -> Result<UserRow, DatabaseError> {
     let user: UserRow = sqlx::query_as(
         r#"
-        SELECT id, name, email, google_id, role, balance, on_leaderboard, private_transactions
+        SELECT id, name, email, google_id, role, balance, on_leaderboard, private_transactions, is_member,
+            low_balance_alerts, low_balance_threshold, email_receipts, monthly_statements
         FROM User 
         WHERE id = ? OR google_id = ?
         "#).bind(user_id).bind(google_id).fetch_one(pool).await?;
@@ -71,15 +77,35 @@ pub async fn set_on_leaderboard(pool: &SqlitePool, user_id: u32, on_leaderboard:
     Ok(())
 }
 
+/// Only the preferences given are changed
+pub async fn set_email_preferences(pool: &SqlitePool, user_id: u32, low_balance_alerts: Option<bool>, low_balance_threshold: Option<f32>, email_receipts: Option<bool>, monthly_statements: Option<bool>) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE User SET
+            low_balance_alerts = COALESCE(?, low_balance_alerts),
+            low_balance_threshold = COALESCE(?, low_balance_threshold),
+            email_receipts = COALESCE(?, email_receipts),
+            monthly_statements = COALESCE(?, monthly_statements)
+        WHERE id = ?
+        "#)
+        .bind(low_balance_alerts)
+        .bind(low_balance_threshold)
+        .bind(email_receipts)
+        .bind(monthly_statements)
+        .bind(user_id).execute(pool).await?;
+    Ok(())
+}
+
 pub async fn update_user(pool: &SqlitePool, user: UserRow) -> Result<(), DatabaseError> {
     sqlx::query(
         r#"
-        UPDATE User SET name = ?, role = ?, balance = ?  
+        UPDATE User SET name = ?, role = ?, balance = ?, is_member = ?
         WHERE id = ?
         "#)
         .bind(user.name)
         .bind(user.role)
         .bind(user.balance)
+        .bind(user.is_member)
         .bind(user.id).execute(pool).await?;
     Ok(())
 }
@@ -152,7 +178,7 @@ pub async fn email_switch_exists(pool: &SqlitePool, user_id: u32) -> Result<bool
         "#
     ).bind(user_id).fetch_one(pool).await?;
 
-    return Ok(exists);
+    Ok(exists)
 }
 
 pub async fn invalidate_email_switch(pool: &SqlitePool, user_id: u32) -> Result<(), DatabaseError> {
@@ -169,7 +195,8 @@ pub async fn invalidate_email_switch(pool: &SqlitePool, user_id: u32) -> Result<
 pub async fn get_users_from_role(pool: &SqlitePool, role: Role) -> Result<Vec<UserRow>, DatabaseError> {
     let users: Vec<UserRow> = sqlx::query_as(
         r#"
-        SELECT id, name, email, google_id, role, balance, on_leaderboard, private_transactions
+        SELECT id, name, email, google_id, role, balance, on_leaderboard, private_transactions, is_member,
+            low_balance_alerts, low_balance_threshold, email_receipts, monthly_statements
         FROM User 
         WHERE role = ?
         "#).bind(role).fetch_all(pool).await?;
@@ -205,26 +232,41 @@ pub async fn finalize_email_switch(pool: &SqlitePool, user_id: u32, new_email: &
 pub async fn create_product(pool: &SqlitePool, mut product: ProductRow) -> Result<ProductRow, DatabaseError> {
     let id: u32 = sqlx::query_scalar(
         r#"
-        INSERT INTO Product (name, price, description, flags)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO Product (name, price, description, category, flags, allergens, nutrition, ingredients, release_at, availability, new_since)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         RETURNING id
         "#
     ).bind(product.name.clone())
     .bind(product.price)
     .bind(product.description.clone())
+    .bind(product.category.clone())
     .bind(product.flags.clone())
+    .bind(product.allergens.clone())
+    .bind(product.nutrition.clone())
+    .bind(product.ingredients.clone())
+    .bind(product.release_at)
+    .bind(product.availability.clone())
+    .bind(product.new_since)
     .fetch_one(pool).await?;
 
     product.id = id;
 
+    create_product_price(pool, id, product.price, UtcDateTime::now().unix_timestamp()).await?;
+
     Ok(product)
 }
 
 pub async fn get_product(pool: &SqlitePool, id: u32) -> Result<ProductRow, DatabaseError> {
     let product: ProductRow = sqlx::query_as(
         r#"
-        SELECT id, name, price, description, stock, flags
-        FROM Product 
+        SELECT id, name, description, category, stock, flags, allergens, nutrition, ingredients, release_at, availability, new_since,
+            COALESCE((
+                SELECT pp.price FROM ProductPrice pp
+                WHERE pp.product = p.id AND pp.effective_from <= strftime('%s', 'now')
+                ORDER BY pp.effective_from DESC, pp.id DESC
+                LIMIT 1
+            ), p.price) AS price
+        FROM Product p
         WHERE id = ?
         "#).bind(id).fetch_one(pool).await?;
     Ok(product)
@@ -233,8 +275,14 @@ pub async fn get_product(pool: &SqlitePool, id: u32) -> Result<ProductRow, Datab
 pub async fn get_products(pool: &SqlitePool) -> Result<Vec<ProductRow>, DatabaseError> {
     let products: Vec<ProductRow> = sqlx::query_as(
         r#"
-        SELECT id, name, price, description, stock, flags
-        FROM Product
+        SELECT id, name, description, category, stock, flags, allergens, nutrition, ingredients, release_at, availability, new_since,
+            COALESCE((
+                SELECT pp.price FROM ProductPrice pp
+                WHERE pp.product = p.id AND pp.effective_from <= strftime('%s', 'now')
+                ORDER BY pp.effective_from DESC, pp.id DESC
+                LIMIT 1
+            ), p.price) AS price
+        FROM Product p
         ORDER BY id DESC
         "#).fetch_all(pool).await?;
 
@@ -248,15 +296,29 @@ pub async fn update_product_data(pool: &SqlitePool, product: ProductRow) -> Resu
             name = ?, 
             price = ?, 
             description = ?,
+            category = ?,
             stock = ?,
-            flags = ?
+            flags = ?,
+            allergens = ?,
+            nutrition = ?,
+            ingredients = ?,
+            release_at = ?,
+            availability = ?,
+            new_since = ?
         WHERE id = ?
         "#)
         .bind(product.name)
         .bind(product.price)
         .bind(product.description)
+        .bind(product.category)
         .bind(product.stock)
         .bind(product.flags)
+        .bind(product.allergens)
+        .bind(product.nutrition)
+        .bind(product.ingredients)
+        .bind(product.release_at)
+        .bind(product.availability)
+        .bind(product.new_since)
         .bind(product.id)
     .execute(pool)
     .await?;
@@ -279,10 +341,48 @@ pub async fn update_product_stock(pool: &SqlitePool, id: u32, stock: Option<i32>
     Ok(())
 }
 
-pub async fn delete_product(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+pub async fn create_product_price(pool: &SqlitePool, product_id: u32, price: f32, effective_from: i64) -> Result<ProductPriceRow, DatabaseError> {
+    let row: ProductPriceRow = sqlx::query_as(
+        r#"
+        INSERT INTO ProductPrice (product, price, effective_from, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING id, product, price, effective_from, created_at
+        "#
+    ).bind(product_id)
+    .bind(price)
+    .bind(effective_from)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .fetch_one(pool).await?;
+
+    Ok(row)
+}
+
+pub async fn get_product_price(pool: &SqlitePool, id: u32) -> Result<ProductPriceRow, DatabaseError> {
+    let row: ProductPriceRow = sqlx::query_as(
+        r#"
+        SELECT id, product, price, effective_from, created_at
+        FROM ProductPrice
+        WHERE id = ?
+        "#).bind(id).fetch_one(pool).await?;
+    Ok(row)
+}
+
+/// All past, current and scheduled prices of a product, newest first
+pub async fn get_price_history(pool: &SqlitePool, product_id: u32) -> Result<Vec<ProductPriceRow>, DatabaseError> {
+    let rows: Vec<ProductPriceRow> = sqlx::query_as(
+        r#"
+        SELECT id, product, price, effective_from, created_at
+        FROM ProductPrice
+        WHERE product = ?
+        ORDER BY effective_from DESC, id DESC
+        "#).bind(product_id).fetch_all(pool).await?;
+    Ok(rows)
+}
+
+pub async fn delete_product_price(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
     sqlx::query(
         r#"
-        DELETE FROM Product 
+        DELETE FROM ProductPrice 
         WHERE id = ?
         "#
     ).bind(id).execute(pool).await?;
@@ -290,225 +390,1486 @@ pub async fn delete_product(pool: &SqlitePool, id: u32) -> Result<(), DatabaseEr
     Ok(())
 }
 
-/// Returns the created transaction's id 
-pub async fn create_transaction(pool: &SqlitePool, transaction: PendingTransaction) -> Result<u32, DatabaseError> {
-    let id: u32 = sqlx::query_scalar(
+pub async fn get_price_tiers(pool: &SqlitePool, product_id: u32) -> Result<Vec<ProductPriceTierRow>, DatabaseError> {
+    let tiers: Vec<ProductPriceTierRow> = sqlx::query_as(
         r#"
-        INSERT INTO StoreTransaction (user, amount, datetime, admin_issued)
-        VALUES (?, ?, ?, ?)
-        RETURNING id
-        "#
-    ).bind(transaction.user)
-    .bind(transaction.amount)
-    .bind(UtcDateTime::now().unix_timestamp())
-    .bind(transaction.admin_issued)
-    .fetch_one(pool).await?;
-    for (product, quantity) in transaction.products {
+        SELECT product, tier, price
+        FROM ProductPriceTier
+        WHERE product = ?
+        "#).bind(product_id).fetch_all(pool).await?;
+    Ok(tiers)
+}
+
+pub async fn get_all_price_tiers(pool: &SqlitePool) -> Result<Vec<ProductPriceTierRow>, DatabaseError> {
+    let tiers: Vec<ProductPriceTierRow> = sqlx::query_as(
+        r#"
+        SELECT product, tier, price
+        FROM ProductPriceTier
+        "#).fetch_all(pool).await?;
+    Ok(tiers)
+}
+
+pub async fn set_price_tiers(pool: &SqlitePool, product_id: u32, tiers: Vec<TierPrice>) -> Result<(), DatabaseError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM ProductPriceTier WHERE product = ?").bind(product_id).execute(&mut *tx).await?;
+    for tier in tiers {
         sqlx::query(
             r#"
-            INSERT INTO TransactionItem (transaction_id, product, quantity, name, price)
-            VALUES (?, ?, ?, ?, ?)
+            INSERT INTO ProductPriceTier (product, tier, price)
+            VALUES (?, ?, ?)
             "#
-        ).bind(id)
-        .bind(product.id)
-        .bind(quantity)
-        .bind(product.name)
-        .bind(product.price).execute(pool).await?;
-    } 
-    Ok(id)
+        ).bind(product_id).bind(tier.tier).bind(tier.price).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
 }
 
-pub async fn delete_transaction(pool: &SqlitePool, transaction_id: u32) -> Result<(), DatabaseError> {
+/// Turns off `new_product` for products flagged as new before `cutoff`
+pub async fn expire_new_products(pool: &SqlitePool, cutoff: i64) -> Result<u64, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE Product SET 
+            flags = json_set(flags, '$.new_product', json('false'))
+        WHERE json_extract(flags, '$.new_product') = 1 AND COALESCE(new_since, 0) < ?
+        "#)
+        .bind(cutoff)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Flags the `count` best selling products since `since` as popular, and unflags the rest
+pub async fn update_popular_products(pool: &SqlitePool, since: i64, count: u32) -> Result<(), DatabaseError> {
     sqlx::query(
         r#"
-        DELETE FROM StoreTransaction 
-        WHERE id = ?
-        "#).bind(transaction_id)
+        UPDATE Product SET 
+            flags = json_set(flags, '$.popular', json(CASE WHEN id IN (
+                SELECT ti.product
+                FROM TransactionItem ti
+                JOIN StoreTransaction st ON st.id = ti.transaction_id
+                WHERE st.datetime > ? AND ti.product IS NOT NULL
+                GROUP BY ti.product
+                ORDER BY SUM(ti.quantity) DESC
+                LIMIT ?
+            ) THEN 'true' ELSE 'false' END))
+        "#)
+        .bind(since)
+        .bind(count)
     .execute(pool)
     .await?;
+
     Ok(())
 }
 
-pub async fn get_transaction(pool: &SqlitePool, transaction_id: u32) -> Result<TransactionRow, DatabaseError> {
-    let transaction: TransactionRow = sqlx::query_as(r#"
-        SELECT id, user, amount, datetime, admin_issued
-        FROM StoreTransaction
+pub async fn delete_product(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM Product 
         WHERE id = ?
-        "#).bind(transaction_id).fetch_one(pool).await?;
-    
-    Ok(transaction)
+        "#
+    ).bind(id).execute(pool).await?;
+
+    Ok(())
 }
 
-pub async fn get_detailed_transaction(pool: &SqlitePool, transaction_id: u32, user: UserRow) -> Result<TransactionDetail, DatabaseError> {
-    let transaction = get_transaction(pool, transaction_id).await?;
+pub async fn create_discount_rule(pool: &SqlitePool, rule: DiscountRuleRow) -> Result<u32, DatabaseError> {
+    let id: u32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO DiscountRule (name, target, discount, starts_at, ends_at, time_window, active)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
+        "#
+    ).bind(rule.name)
+    .bind(rule.target)
+    .bind(rule.discount)
+    .bind(rule.starts_at)
+    .bind(rule.ends_at)
+    .bind(rule.time_window)
+    .bind(rule.active)
+    .fetch_one(pool).await?;
 
-    let mut detailed_transaction = TransactionDetail::create(transaction, user);
+    Ok(id)
+}
 
-    let items: Vec<TransactionItemRow> = sqlx::query_as(r#"
-        SELECT id, transaction_id, product, quantity, name, price
-        FROM TransactionItem
-        WHERE transaction_id = ?
-        "#).bind(transaction_id).fetch_all(pool).await?;
+pub async fn get_discount_rules(pool: &SqlitePool) -> Result<Vec<DiscountRuleRow>, DatabaseError> {
+    let rules: Vec<DiscountRuleRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, target, discount, starts_at, ends_at, time_window, active
+        FROM DiscountRule
+        ORDER BY id DESC
+        "#).fetch_all(pool).await?;
+    Ok(rules)
+}
 
-    detailed_transaction.add_items(items);
+/// Active rules whose campaign period contains `now`. Time windows are not checked
+pub async fn get_active_discount_rules(pool: &SqlitePool, now: i64) -> Result<Vec<DiscountRuleRow>, DatabaseError> {
+    let rules: Vec<DiscountRuleRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, target, discount, starts_at, ends_at, time_window, active
+        FROM DiscountRule
+        WHERE active = 1 
+            AND (starts_at IS NULL OR starts_at <= ?) 
+            AND (ends_at IS NULL OR ends_at > ?)
+        "#).bind(now).bind(now).fetch_all(pool).await?;
+    Ok(rules)
+}
 
-    Ok(detailed_transaction)
+pub async fn update_discount_rule(pool: &SqlitePool, rule: DiscountRuleRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE DiscountRule SET 
+            name = ?,
+            target = ?,
+            discount = ?,
+            starts_at = ?,
+            ends_at = ?,
+            time_window = ?,
+            active = ?
+        WHERE id = ?
+        "#)
+        .bind(rule.name)
+        .bind(rule.target)
+        .bind(rule.discount)
+        .bind(rule.starts_at)
+        .bind(rule.ends_at)
+        .bind(rule.time_window)
+        .bind(rule.active)
+        .bind(rule.id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
 }
 
-const PAYMENT_KEYWORDS: [&str; 4] = [
-    "swish", "insättning", "deposit", "payment"
-];
+pub async fn delete_discount_rule(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM DiscountRule 
+        WHERE id = ?
+        "#
+    ).bind(id).execute(pool).await?;
 
-pub async fn query_transactions(pool: &SqlitePool, query: TransactionQuery) -> Result<Vec<TransactionSummary>, DatabaseError> {
-    let mut builder = QueryBuilder::new(r#"
-        SELECT st.id, u.email AS user_email, st.amount, st.datetime, st.admin_issued
-        FROM StoreTransaction st
-        LEFT JOIN User u ON u.id = st.user
-        WHERE 1=1
-        "#);
-    // LEFT JOIN will set user_email to NULL if StoreTransaction has user field set to NULL
-    // (private_transactions)
-    
-    // OR users
-    if !query.user_ids.is_empty() {
-        builder.push(" AND st.user IN (");
+    Ok(())
+}
 
-        let mut sep = builder.separated(", ");
-        for id in query.user_ids {
-            sep.push_bind(id);
-        }
-        drop(sep);
+pub async fn create_bundle(pool: &SqlitePool, bundle: BundleRow, components: Vec<BundleComponentRow>) -> Result<u32, DatabaseError> {
+    let mut tx = pool.begin().await?;
+    let id: u32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO Bundle (name, price, auto_apply, active)
+        VALUES (?, ?, ?, ?)
+        RETURNING id
+        "#
+    ).bind(bundle.name)
+    .bind(bundle.price)
+    .bind(bundle.auto_apply)
+    .bind(bundle.active)
+    .fetch_one(&mut *tx).await?;
 
-        builder.push(")");
+    for component in components {
+        sqlx::query(
+            r#"
+            INSERT INTO BundleComponent (bundle, product, quantity)
+            VALUES (?, ?, ?)
+            "#
+        ).bind(id).bind(component.product).bind(component.quantity).execute(&mut *tx).await?;
     }
 
-    // OR products
-    if !query.product_ids.is_empty() {
-        builder.push(" AND EXISTS (SELECT 1 FROM TransactionItem item WHERE item.product IN (");
-
-        let mut sep = builder.separated(", ");
-        for id in query.product_ids {
-            sep.push_bind(id);
-        }
-        drop(sep);
+    tx.commit().await?;
 
-        builder.push(") AND item.transaction_id = st.id)");
-    }
+    Ok(id)
+}
 
-    // BETWEEN time range
-    if let Some(time_range) = query.time_range {
-        time_range.push_onto_builder(&mut builder, " AND ");
-    }
+pub async fn get_bundles(pool: &SqlitePool) -> Result<Vec<Bundle>, DatabaseError> {
+    let rows: Vec<BundleRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, price, auto_apply, active
+        FROM Bundle
+        ORDER BY id DESC
+        "#).fetch_all(pool).await?;
 
-    // ADMIN ISSUED
-    if let Some(admin_issued) = query.admin_issued {
-        builder.push(" AND st.admin_issued = ").push_bind(admin_issued);
-    }
+    let components: Vec<BundleComponentRow> = sqlx::query_as(
+        r#"
+        SELECT bundle, product, quantity
+        FROM BundleComponent
+        "#).fetch_all(pool).await?;
 
-    // CURSOR
-    if let Some(cursor) = query.cursor {
-        let cmp = if query.descending { '<' } else { '>' };
-        builder.push(format!(" AND (st.datetime {cmp} ")).push_bind(cursor.datetime);
-        builder.push(" OR (st.datetime = ").push_bind(cursor.datetime);
-        builder.push(format!(" AND st.id {cmp} ")).push_bind(cursor.id);
-        builder.push("))");
-    }
+    let bundles = rows.into_iter().map(|row| {
+        let bundle_components = components.iter().filter(|c| c.bundle == row.id).cloned().collect();
+        Bundle::from_rows(row, bundle_components)
+    }).collect();
 
-    // SEARCH by fts table + keywords
-    if let Some(search_term) = query.search_term {
+    Ok(bundles)
+}
 
-        let (terms, found_payment_keyword) = search_term.split(|c: char| !c.is_alphanumeric())
-            .filter(|w| !w.is_empty())
-            .map(|w| w.to_lowercase())
-            .fold((Vec::new(), false), |(mut terms, mut payment_kw), w| {
-                if PAYMENT_KEYWORDS.contains(&w.as_str()) {
-                    payment_kw = payment_kw || true;
-                } else {
-                    terms.push(format!("\"{w}\"*"));
-                }
-                (terms, payment_kw)
-            });
-        
-        for term in &terms {
-            builder.push(" AND EXISTS (SELECT 1 FROM TransactionFts WHERE transaction_id = st.id");
-            builder.push(" AND TransactionFts MATCH ").push_bind(term.clone());
-            builder.push(")");
-        }
+pub async fn update_bundle(pool: &SqlitePool, bundle: BundleRow, components: Vec<BundleComponentRow>) -> Result<(), DatabaseError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE Bundle SET 
+            name = ?,
+            price = ?,
+            auto_apply = ?,
+            active = ?
+        WHERE id = ?
+        "#)
+        .bind(bundle.name)
+        .bind(bundle.price)
+        .bind(bundle.auto_apply)
+        .bind(bundle.active)
+        .bind(bundle.id)
+    .execute(&mut *tx)
+    .await?;
 
-        if found_payment_keyword {
-            builder.push(" AND st.amount > 0");
-        }
+    sqlx::query("DELETE FROM BundleComponent WHERE bundle = ?").bind(bundle.id).execute(&mut *tx).await?;
+    for component in components {
+        sqlx::query(
+            r#"
+            INSERT INTO BundleComponent (bundle, product, quantity)
+            VALUES (?, ?, ?)
+            "#
+        ).bind(bundle.id).bind(component.product).bind(component.quantity).execute(&mut *tx).await?;
     }
 
-    // ORDER
-    if query.descending {
-        builder.push(" ORDER BY st.datetime DESC, st.id DESC");
-    } else {
-        builder.push(" ORDER BY st.datetime ASC, st.id ASC");
-    }
+    tx.commit().await?;
 
-    // LIMIT 
-    builder.push(" LIMIT ").push_bind(query.limit);
-    
-    let transactions: Vec<TransactionSummary> = builder.build_query_as().fetch_all(pool).await?;
-    
-    Ok(transactions)
+    Ok(())
 }
 
-pub async fn unlink_transactions(pool: &SqlitePool, user_id: u32) -> Result<(), DatabaseError> {
-    sqlx::query(
-        r#"
-        UPDATE StoreTransaction
-        SET user = NULL
-        WHERE user = ?
-        "#).bind(user_id).execute(pool).await?;
+pub async fn delete_bundle(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
     sqlx::query(
         r#"
-        DELETE FROM TransactionFts WHERE rowid IN (
-            SELECT rowid FROM TransactionFts WHERE user_id = ?
-        )
-        "#).bind(user_id).execute(pool).await?;
+        DELETE FROM Bundle 
+        WHERE id = ?
+        "#
+    ).bind(id).execute(pool).await?;
+
     Ok(())
 }
 
-//
-//          Payment
-//
-
-pub async fn create_payment_request(pool: &SqlitePool, row: SwishPaymentRequestRow) -> Result<(), DatabaseError> {
-    sqlx::query(
+pub async fn create_stamp_program(pool: &SqlitePool, program: StampCardProgramRow) -> Result<u32, DatabaseError> {
+    let id: u32 = sqlx::query_scalar(
         r#"
-        INSERT INTO SwishPaymentRequest (id, user, amount, status, token, callback_identifier, location)
-        VALUES (?, ?, ?, ?, ?, ?, ?)
+        INSERT INTO StampCardProgram (name, target, stamps_required, active)
+        VALUES (?, ?, ?, ?)
+        RETURNING id
         "#
-    ).bind(row.id)
-    .bind(row.user)
-    .bind(row.amount)
+    ).bind(program.name)
+    .bind(program.target)
+    .bind(program.stamps_required)
+    .bind(program.active)
+    .fetch_one(pool).await?;
+
+    Ok(id)
+}
+
+pub async fn get_stamp_programs(pool: &SqlitePool) -> Result<Vec<StampCardProgramRow>, DatabaseError> {
+    let programs: Vec<StampCardProgramRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, target, stamps_required, active
+        FROM StampCardProgram
+        ORDER BY id DESC
+        "#).fetch_all(pool).await?;
+    Ok(programs)
+}
+
+pub async fn update_stamp_program(pool: &SqlitePool, program: StampCardProgramRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE StampCardProgram SET 
+            name = ?,
+            target = ?,
+            stamps_required = ?,
+            active = ?
+        WHERE id = ?
+        "#)
+        .bind(program.name)
+        .bind(program.target)
+        .bind(program.stamps_required)
+        .bind(program.active)
+        .bind(program.id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_stamp_program(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM StampCardProgram 
+        WHERE id = ?
+        "#
+    ).bind(id).execute(pool).await?;
+
+    Ok(())
+}
+
+/// The user's total number of stamps on each program they have stamps on
+pub async fn get_stamp_totals(pool: &SqlitePool, user_id: u32) -> Result<Vec<(u32, i64)>, DatabaseError> {
+    let totals: Vec<(u32, i64)> = sqlx::query_as(
+        r#"
+        SELECT program, SUM(stamps)
+        FROM StampEntry
+        WHERE user = ?
+        GROUP BY program
+        "#).bind(user_id).fetch_all(pool).await?;
+    Ok(totals)
+}
+
+pub async fn add_stamps(pool: &SqlitePool, user_id: u32, program_id: u32, transaction_id: Option<u32>, stamps: i64) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO StampEntry (user, program, transaction_id, stamps, datetime)
+        VALUES (?, ?, ?, ?, ?)
+        "#
+    ).bind(user_id)
+    .bind(program_id)
+    .bind(transaction_id)
+    .bind(stamps)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .execute(pool).await?;
+
+    Ok(())
+}
+
+pub async fn create_deposit_bonus_rule(pool: &SqlitePool, rule: DepositBonusRuleRow) -> Result<u32, DatabaseError> {
+    let id: u32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO DepositBonusRule (name, bonus, min_amount, starts_at, ends_at, active)
+        VALUES (?, ?, ?, ?, ?, ?)
+        RETURNING id
+        "#
+    ).bind(rule.name)
+    .bind(rule.bonus)
+    .bind(rule.min_amount)
+    .bind(rule.starts_at)
+    .bind(rule.ends_at)
+    .bind(rule.active)
+    .fetch_one(pool).await?;
+
+    Ok(id)
+}
+
+pub async fn get_deposit_bonus_rules(pool: &SqlitePool) -> Result<Vec<DepositBonusRuleRow>, DatabaseError> {
+    let rules: Vec<DepositBonusRuleRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, bonus, min_amount, starts_at, ends_at, active
+        FROM DepositBonusRule
+        ORDER BY id DESC
+        "#).fetch_all(pool).await?;
+    Ok(rules)
+}
+
+/// Active rules whose campaign period contains `now`
+pub async fn get_active_deposit_bonus_rules(pool: &SqlitePool, now: i64) -> Result<Vec<DepositBonusRuleRow>, DatabaseError> {
+    let rules: Vec<DepositBonusRuleRow> = sqlx::query_as(
+        r#"
+        SELECT id, name, bonus, min_amount, starts_at, ends_at, active
+        FROM DepositBonusRule
+        WHERE active = 1 
+            AND (starts_at IS NULL OR starts_at <= ?) 
+            AND (ends_at IS NULL OR ends_at > ?)
+        "#).bind(now).bind(now).fetch_all(pool).await?;
+    Ok(rules)
+}
+
+pub async fn update_deposit_bonus_rule(pool: &SqlitePool, rule: DepositBonusRuleRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE DepositBonusRule SET 
+            name = ?,
+            bonus = ?,
+            min_amount = ?,
+            starts_at = ?,
+            ends_at = ?,
+            active = ?
+        WHERE id = ?
+        "#)
+        .bind(rule.name)
+        .bind(rule.bonus)
+        .bind(rule.min_amount)
+        .bind(rule.starts_at)
+        .bind(rule.ends_at)
+        .bind(rule.active)
+        .bind(rule.id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_deposit_bonus_rule(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM DepositBonusRule 
+        WHERE id = ?
+        "#
+    ).bind(id).execute(pool).await?;
+
+    Ok(())
+}
+
+pub async fn create_voucher(pool: &SqlitePool, voucher: VoucherRow) -> Result<u32, DatabaseError> {
+    let id: u32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO Voucher (code, value, max_redemptions, expires_at, note, created_by, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
+        "#
+    ).bind(voucher.code)
+    .bind(voucher.value)
+    .bind(voucher.max_redemptions)
+    .bind(voucher.expires_at)
+    .bind(voucher.note)
+    .bind(voucher.created_by)
+    .bind(voucher.created_at)
+    .fetch_one(pool).await?;
+
+    Ok(id)
+}
+
+pub async fn get_vouchers(pool: &SqlitePool) -> Result<Vec<VoucherRow>, DatabaseError> {
+    let vouchers: Vec<VoucherRow> = sqlx::query_as(
+        r#"
+        SELECT id, code, value, max_redemptions, expires_at, note, created_by, created_at
+        FROM Voucher
+        ORDER BY id DESC
+        "#).fetch_all(pool).await?;
+    Ok(vouchers)
+}
+
+pub async fn get_voucher_by_code(pool: &SqlitePool, code: &str) -> Result<Option<VoucherRow>, DatabaseError> {
+    let voucher: Option<VoucherRow> = sqlx::query_as(
+        r#"
+        SELECT id, code, value, max_redemptions, expires_at, note, created_by, created_at
+        FROM Voucher
+        WHERE code = ?
+        "#).bind(code).fetch_optional(pool).await?;
+    Ok(voucher)
+}
+
+pub async fn get_voucher_redemptions(pool: &SqlitePool) -> Result<Vec<VoucherRedemptionRow>, DatabaseError> {
+    let redemptions: Vec<VoucherRedemptionRow> = sqlx::query_as(
+        r#"
+        SELECT r.voucher, r.user, u.email AS user_email, r.transaction_id, r.redeemed_at
+        FROM VoucherRedemption r
+        LEFT JOIN User u ON u.id = r.user
+        ORDER BY r.redeemed_at ASC
+        "#).fetch_all(pool).await?;
+    Ok(redemptions)
+}
+
+pub async fn has_redeemed_voucher(pool: &SqlitePool, voucher_id: u32, user_id: u32) -> Result<bool, DatabaseError> {
+    let redeemed: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM VoucherRedemption WHERE voucher = ? AND user = ?)
+        "#).bind(voucher_id).bind(user_id).fetch_one(pool).await?;
+    Ok(redeemed)
+}
+
+/// Claims one redemption of the voucher for the user.
+/// Returns the redemption's id, or None if the voucher has no redemptions left
+pub async fn claim_voucher(pool: &SqlitePool, voucher_id: u32, user_id: u32) -> Result<Option<u32>, DatabaseError> {
+    // Counting and inserting in one statement keeps concurrent redemptions from exceeding the limit
+    let id: Option<u32> = sqlx::query_scalar(
+        r#"
+        INSERT INTO VoucherRedemption (voucher, user, redeemed_at)
+        SELECT v.id, ?, ?
+        FROM Voucher v
+        WHERE v.id = ? 
+            AND (SELECT COUNT(*) FROM VoucherRedemption r WHERE r.voucher = v.id) < v.max_redemptions
+        RETURNING id
+        "#
+    ).bind(user_id)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .bind(voucher_id)
+    .fetch_optional(pool).await?;
+
+    Ok(id)
+}
+
+pub async fn set_redemption_transaction(pool: &SqlitePool, redemption_id: u32, transaction_id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE VoucherRedemption
+        SET transaction_id = ?
+        WHERE id = ?
+        "#
+    ).bind(transaction_id).bind(redemption_id).execute(pool).await?;
+    Ok(())
+}
+
+pub async fn delete_voucher(pool: &SqlitePool, id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM Voucher 
+        WHERE id = ?
+        "#
+    ).bind(id).execute(pool).await?;
+
+    Ok(())
+}
+
+/// Returns the created transaction's id 
+pub async fn create_transaction(pool: &SqlitePool, transaction: PendingTransaction) -> Result<u32, DatabaseError> {
+    let id: u32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO StoreTransaction (user, amount, datetime, admin_issued, price_tier, kind, payment_reference, note, payment_method)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
+        "#
+    ).bind(transaction.user)
+    .bind(transaction.amount)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .bind(transaction.admin_issued)
+    .bind(transaction.price_tier)
+    .bind(transaction.kind)
+    .bind(transaction.payment_reference)
+    .bind(transaction.note)
+    .bind(transaction.payment_method)
+    .fetch_one(pool).await?;
+    for item in transaction.products {
+        sqlx::query(
+            r#"
+            INSERT INTO TransactionItem (transaction_id, product, quantity, name, price, discount, discount_rule, bundle, price_tier, stamp_program)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#
+        ).bind(id)
+        .bind(item.product.id)
+        .bind(item.quantity)
+        .bind(item.product.name)
+        .bind(item.product.price)
+        .bind(item.discount)
+        .bind(item.discount_rule)
+        .bind(item.bundle)
+        .bind(item.price_tier)
+        .bind(item.stamp_program).execute(pool).await?;
+    } 
+    Ok(id)
+}
+
+pub async fn delete_transaction(pool: &SqlitePool, transaction_id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM StoreTransaction 
+        WHERE id = ?
+        "#).bind(transaction_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_transaction(pool: &SqlitePool, transaction_id: u32) -> Result<TransactionRow, DatabaseError> {
+    let transaction: TransactionRow = sqlx::query_as(r#"
+        SELECT id, user, amount, datetime, admin_issued, price_tier, kind, payment_reference, note, payment_method
+        FROM StoreTransaction
+        WHERE id = ?
+        "#).bind(transaction_id).fetch_one(pool).await?;
+    
+    Ok(transaction)
+}
+
+pub async fn get_transaction_items(pool: &SqlitePool, transaction_id: u32) -> Result<Vec<TransactionItemRow>, DatabaseError> {
+    let items = sqlx::query_as(r#"
+        SELECT id, transaction_id, product, quantity, name, price, discount, discount_rule, bundle, price_tier, stamp_program
+        FROM TransactionItem
+        WHERE transaction_id = ?
+        "#).bind(transaction_id).fetch_all(pool).await?;
+    Ok(items)
+}
+
+pub async fn get_detailed_transaction(pool: &SqlitePool, transaction_id: u32, user: UserRow) -> Result<TransactionDetail, DatabaseError> {
+    let transaction = get_transaction(pool, transaction_id).await?;
+
+    let mut detailed_transaction = TransactionDetail::create(transaction, user);
+
+    let items = get_transaction_items(pool, transaction_id).await?;
+    detailed_transaction.add_items(items);
+
+    Ok(detailed_transaction)
+}
+
+const PAYMENT_KEYWORDS: [&str; 4] = [
+    "swish", "insättning", "deposit", "payment"
+];
+
+pub async fn query_transactions(pool: &SqlitePool, query: TransactionQuery) -> Result<Vec<TransactionSummary>, DatabaseError> {
+    let mut builder = QueryBuilder::new(r#"
+        SELECT st.id, u.email AS user_email, st.amount, st.datetime, st.admin_issued, st.kind, st.note, st.payment_method
+        FROM StoreTransaction st
+        LEFT JOIN User u ON u.id = st.user
+        WHERE 1=1
+        "#);
+    // LEFT JOIN will set user_email to NULL if StoreTransaction has user field set to NULL
+    // (private_transactions)
+    
+    // OR users
+    if !query.user_ids.is_empty() {
+        builder.push(" AND st.user IN (");
+
+        let mut sep = builder.separated(", ");
+        for id in query.user_ids {
+            sep.push_bind(id);
+        }
+
+        builder.push(")");
+    }
+
+    // OR products
+    if !query.product_ids.is_empty() {
+        builder.push(" AND EXISTS (SELECT 1 FROM TransactionItem item WHERE item.product IN (");
+
+        let mut sep = builder.separated(", ");
+        for id in query.product_ids {
+            sep.push_bind(id);
+        }
+
+        builder.push(") AND item.transaction_id = st.id)");
+    }
+
+    // BETWEEN time range
+    if let Some(time_range) = query.time_range {
+        time_range.push_onto_builder(&mut builder, " AND ");
+    }
+
+    // ADMIN ISSUED
+    if let Some(admin_issued) = query.admin_issued {
+        builder.push(" AND st.admin_issued = ").push_bind(admin_issued);
+    }
+
+    // OR kinds
+    if !query.kinds.is_empty() {
+        builder.push(" AND st.kind IN (");
+
+        let mut sep = builder.separated(", ");
+        for kind in query.kinds {
+            sep.push_bind(kind);
+        }
+
+        builder.push(")");
+    }
+
+    // CURSOR
+    if let Some(cursor) = query.cursor {
+        let cmp = if query.descending { '<' } else { '>' };
+        builder.push(format!(" AND (st.datetime {cmp} ")).push_bind(cursor.datetime);
+        builder.push(" OR (st.datetime = ").push_bind(cursor.datetime);
+        builder.push(format!(" AND st.id {cmp} ")).push_bind(cursor.id);
+        builder.push("))");
+    }
+
+    // SEARCH by fts table + keywords
+    if let Some(search_term) = query.search_term {
+
+        let (terms, found_payment_keyword) = search_term.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_lowercase())
+            .fold((Vec::new(), false), |(mut terms, mut payment_kw), w| {
+                if PAYMENT_KEYWORDS.contains(&w.as_str()) {
+                    payment_kw = true;
+                } else {
+                    terms.push(format!("\"{w}\"*"));
+                }
+                (terms, payment_kw)
+            });
+        
+        for term in &terms {
+            builder.push(" AND EXISTS (SELECT 1 FROM TransactionFts WHERE transaction_id = st.id");
+            builder.push(" AND TransactionFts MATCH ").push_bind(term.clone());
+            builder.push(")");
+        }
+
+        if found_payment_keyword {
+            builder.push(" AND st.amount > 0");
+        }
+    }
+
+    // ORDER
+    if query.descending {
+        builder.push(" ORDER BY st.datetime DESC, st.id DESC");
+    } else {
+        builder.push(" ORDER BY st.datetime ASC, st.id ASC");
+    }
+
+    // LIMIT 
+    builder.push(" LIMIT ").push_bind(query.limit);
+    
+    let transactions: Vec<TransactionSummary> = builder.build_query_as().fetch_all(pool).await?;
+    
+    Ok(transactions)
+}
+
+pub async fn unlink_transactions(pool: &SqlitePool, user_id: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE StoreTransaction
+        SET user = NULL
+        WHERE user = ?
+        "#).bind(user_id).execute(pool).await?;
+    sqlx::query(
+        r#"
+        DELETE FROM TransactionFts WHERE rowid IN (
+            SELECT rowid FROM TransactionFts WHERE user_id = ?
+        )
+        "#).bind(user_id).execute(pool).await?;
+    sqlx::query(
+        r#"
+        UPDATE StampEntry
+        SET transaction_id = NULL
+        WHERE user = ?
+        "#).bind(user_id).execute(pool).await?;
+    Ok(())
+}
+
+//
+//          Payment
+//
+
+pub async fn create_payment_request(pool: &SqlitePool, row: SwishPaymentRequestRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO SwishPaymentRequest (id, user, amount, status, token, callback_identifier, location, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#
+    ).bind(row.id)
+    .bind(row.user)
+    .bind(row.amount)
+    .bind(row.status)
+    .bind(row.token)
+    .bind(row.callback_identifier)
+    .bind(row.location)
+    .bind(row.created_at)
+    .bind(row.updated_at)
+    .execute(pool).await?;
+
+    Ok(())
+}
+
+pub async fn get_payment_request(pool: &SqlitePool, payment_id: String) -> Result<SwishPaymentRequestRow, DatabaseError> {
+    let payment_request = sqlx::query_as(
+        r#"
+        SELECT * FROM SwishPaymentRequest WHERE id = ?
+        "#
+    ).bind(payment_id).fetch_one(pool).await?;
+    Ok(payment_request)
+}
+
+/// Moves the request from status `from` to `to`. Returns false if it was no longer in `from`,
+/// so a payment reported by both the callback and the poller is only handled once
+pub async fn update_payment_request(pool: &SqlitePool, payment_id: String, from: swish::Status, to: swish::Status) -> Result<bool, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE SwishPaymentRequest
+        SET status = ?1, updated_at = ?2, paid_at = CASE WHEN ?1 = 'paid' THEN ?2 ELSE paid_at END
+        WHERE id = ?3 AND status = ?4
+        "#
+    ).bind(to).bind(UtcDateTime::now().unix_timestamp()).bind(payment_id).bind(from).execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stores why Swish says a payment failed, shown to the user in their payment history
+pub async fn set_payment_error(pool: &SqlitePool, payment_id: String, error_code: Option<String>, error_message: Option<String>) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE SwishPaymentRequest
+        SET error_code = ?, error_message = ?
+        WHERE id = ?
+        "#
+    ).bind(error_code).bind(error_message).bind(payment_id).execute(pool).await?;
+    Ok(())
+}
+
+/// Payment requests matching `query`, newest first
+pub async fn query_payment_requests(pool: &SqlitePool, query: PaymentRequestQuery) -> Result<Vec<SwishPaymentSummaryRow>, DatabaseError> {
+    let mut builder = QueryBuilder::new(r#"
+        SELECT spr.id, spr.user, u.email AS user_email, spr.amount, spr.status, spr.payment_reference,
+            spr.error_code, spr.error_message, spr.created_at, spr.updated_at, spr.paid_at,
+            COALESCE((SELECT SUM(r.amount) FROM SwishRefund r WHERE r.payment_request = spr.id AND r.status != 'error'), 0.0) AS refunded
+        FROM SwishPaymentRequest spr
+        JOIN User u ON u.id = spr.user
+        WHERE 1=1
+        "#);
+
+    // OR users
+    if !query.user_ids.is_empty() {
+        builder.push(" AND spr.user IN (");
+        let mut sep = builder.separated(", ");
+        for id in query.user_ids {
+            sep.push_bind(id);
+        }
+        builder.push(")");
+    }
+
+    // OR statuses
+    if !query.statuses.is_empty() {
+        builder.push(" AND spr.status IN (");
+        let mut sep = builder.separated(", ");
+        for status in query.statuses {
+            sep.push_bind(status);
+        }
+        builder.push(")");
+    }
+
+    if let Some(start) = query.created_after {
+        builder.push(" AND spr.created_at >= ").push_bind(start);
+    }
+    if let Some(end) = query.created_before {
+        builder.push(" AND spr.created_at < ").push_bind(end);
+    }
+    if let Some(payment_reference) = query.payment_reference {
+        builder.push(" AND spr.payment_reference = ").push_bind(payment_reference);
+    }
+
+    builder.push(" ORDER BY spr.created_at DESC, spr.id DESC LIMIT ").push_bind(query.limit);
+    builder.push(" OFFSET ").push_bind(query.offset);
+
+    let payment_requests = builder.build_query_as().fetch_all(pool).await?;
+    Ok(payment_requests)
+}
+
+/// Pending requests created before `created_before`
+pub async fn get_stale_payment_requests(pool: &SqlitePool, created_before: i64) -> Result<Vec<SwishPaymentRequestRow>, DatabaseError> {
+    let payment_requests = sqlx::query_as(
+        r#"
+        SELECT * FROM SwishPaymentRequest WHERE status = 'pending' AND created_at < ?
+        "#
+    ).bind(created_before).fetch_all(pool).await?;
+    Ok(payment_requests)
+}
+
+pub async fn create_cash_deposit(pool: &SqlitePool, user: u32, amount: f32, received_by: u32) -> Result<CashDepositRow, DatabaseError> {
+    let deposit = sqlx::query_as(
+        r#"
+        INSERT INTO CashDeposit (user, amount, received_by, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING *
+        "#
+    ).bind(user).bind(amount).bind(received_by).bind(UtcDateTime::now().unix_timestamp())
+    .fetch_one(pool).await?;
+    Ok(deposit)
+}
+
+pub async fn get_cash_deposit(pool: &SqlitePool, id: u32) -> Result<CashDepositRow, DatabaseError> {
+    let deposit = sqlx::query_as(
+        r#"
+        SELECT * FROM CashDeposit WHERE id = ?
+        "#
+    ).bind(id).fetch_one(pool).await?;
+    Ok(deposit)
+}
+
+/// Cash deposits registered within `start..end`, newest first
+pub async fn get_cash_deposits(pool: &SqlitePool, start: i64, end: i64) -> Result<Vec<CashDepositRow>, DatabaseError> {
+    let deposits = sqlx::query_as(
+        r#"
+        SELECT * FROM CashDeposit WHERE created_at >= ? AND created_at < ? ORDER BY created_at DESC
+        "#
+    ).bind(start).bind(end).fetch_all(pool).await?;
+    Ok(deposits)
+}
+
+pub async fn add_cash_refund(pool: &SqlitePool, id: u32, amount: f32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE CashDeposit SET refunded = refunded + ? WHERE id = ?
+        "#
+    ).bind(amount).bind(id).execute(pool).await?;
+    Ok(())
+}
+
+pub async fn create_cash_withdrawal(pool: &SqlitePool, amount: f32, note: String, created_by: u32) -> Result<CashWithdrawalRow, DatabaseError> {
+    let withdrawal = sqlx::query_as(
+        r#"
+        INSERT INTO CashWithdrawal (amount, note, created_by, created_at)
+        VALUES (?, ?, ?, ?)
+        RETURNING *
+        "#
+    ).bind(amount).bind(note).bind(created_by).bind(UtcDateTime::now().unix_timestamp())
+    .fetch_one(pool).await?;
+    Ok(withdrawal)
+}
+
+pub async fn get_cash_withdrawals(pool: &SqlitePool, start: i64, end: i64) -> Result<Vec<CashWithdrawalRow>, DatabaseError> {
+    let withdrawals = sqlx::query_as(
+        r#"
+        SELECT * FROM CashWithdrawal WHERE created_at >= ? AND created_at < ? ORDER BY created_at ASC
+        "#
+    ).bind(start).bind(end).fetch_all(pool).await?;
+    Ok(withdrawals)
+}
+
+pub async fn create_cash_count(pool: &SqlitePool, counted: f32, expected: f32, note: Option<String>, counted_by: u32) -> Result<CashCountRow, DatabaseError> {
+    let count = sqlx::query_as(
+        r#"
+        INSERT INTO CashCount (counted, expected, note, counted_by, created_at, transaction_id, withdrawal_id)
+        VALUES (?, ?, ?, ?, ?,
+            (SELECT COALESCE(MAX(id), 0) FROM StoreTransaction),
+            (SELECT COALESCE(MAX(id), 0) FROM CashWithdrawal))
+        RETURNING *
+        "#
+    ).bind(counted).bind(expected).bind(note).bind(counted_by).bind(UtcDateTime::now().unix_timestamp())
+    .fetch_one(pool).await?;
+    Ok(count)
+}
+
+pub async fn get_cash_counts(pool: &SqlitePool, start: i64, end: i64) -> Result<Vec<CashCountRow>, DatabaseError> {
+    let counts = sqlx::query_as(
+        r#"
+        SELECT * FROM CashCount WHERE created_at >= ? AND created_at < ? ORDER BY created_at ASC, id ASC
+        "#
+    ).bind(start).bind(end).fetch_all(pool).await?;
+    Ok(counts)
+}
+
+/// The latest count made before `before`
+pub async fn get_last_cash_count(pool: &SqlitePool, before: i64) -> Result<Option<CashCountRow>, DatabaseError> {
+    let count = sqlx::query_as(
+        r#"
+        SELECT * FROM CashCount WHERE created_at < ? ORDER BY id DESC LIMIT 1
+        "#
+    ).bind(before).fetch_optional(pool).await?;
+    Ok(count)
+}
+
+/// Cash deposits, cash refunds and withdrawals within `start..end`, only those made after `after` if given
+pub async fn get_cash_flow(pool: &SqlitePool, start: i64, end: i64, after: Option<&CashCountRow>) -> Result<CashFlowRow, DatabaseError> {
+    let flow = sqlx::query_as(
+        r#"
+        SELECT
+            COALESCE((SELECT SUM(amount) FROM StoreTransaction
+                WHERE payment_method = 'cash' AND kind = 'deposit' AND datetime >= ?1 AND datetime < ?2 AND id > ?3), 0.0) AS deposits,
+            COALESCE((SELECT -SUM(amount) FROM StoreTransaction
+                WHERE payment_method = 'cash' AND kind = 'refund' AND datetime >= ?1 AND datetime < ?2 AND id > ?3), 0.0) AS refunds,
+            COALESCE((SELECT SUM(amount) FROM CashWithdrawal
+                WHERE created_at >= ?1 AND created_at < ?2 AND id > ?4), 0.0) AS withdrawals
+        "#
+    ).bind(start)
+    .bind(end)
+    .bind(after.map_or(0, |count| count.transaction_id))
+    .bind(after.map_or(0, |count| count.withdrawal_id))
+    .fetch_one(pool).await?;
+    Ok(flow)
+}
+
+/// Totals of ledger entries of `kind` within `start..end` grouped by payment method
+pub async fn get_payment_method_totals(pool: &SqlitePool, kind: TransactionKind, start: i64, end: i64) -> Result<Vec<PaymentMethodTotalRow>, DatabaseError> {
+    let totals = sqlx::query_as(
+        r#"
+        SELECT payment_method AS method, COUNT(*) AS count, COALESCE(SUM(amount), 0.0) AS total
+        FROM StoreTransaction
+        WHERE kind = ? AND datetime >= ? AND datetime < ?
+        GROUP BY payment_method
+        ORDER BY total DESC
+        "#
+    ).bind(kind).bind(start).bind(end).fetch_all(pool).await?;
+    Ok(totals)
+}
+
+pub async fn get_sales(pool: &SqlitePool, start: i64, end: i64) -> Result<SalesRow, DatabaseError> {
+    let sales = sqlx::query_as(
+        r#"
+        SELECT COUNT(*) AS count, -COALESCE(SUM(amount), 0.0) AS total
+        FROM StoreTransaction
+        WHERE kind = 'purchase' AND datetime >= ? AND datetime < ?
+        "#
+    ).bind(start).bind(end).fetch_one(pool).await?;
+    Ok(sales)
+}
+
+pub async fn create_payout(pool: &SqlitePool, user: u32, amount: f32, method: PaymentMethod, destination: Option<String>) -> Result<PayoutRow, DatabaseError> {
+    let payout = sqlx::query_as(
+        r#"
+        INSERT INTO Payout (user, amount, method, destination, status, requested_at)
+        VALUES (?, ?, ?, ?, 'requested', ?)
+        RETURNING *
+        "#
+    ).bind(user).bind(amount).bind(method).bind(destination).bind(UtcDateTime::now().unix_timestamp())
+    .fetch_one(pool).await?;
+    Ok(payout)
+}
+
+pub async fn get_payout(pool: &SqlitePool, id: u32) -> Result<PayoutRow, DatabaseError> {
+    let payout = sqlx::query_as(
+        r#"
+        SELECT * FROM Payout WHERE id = ?
+        "#
+    ).bind(id).fetch_one(pool).await?;
+    Ok(payout)
+}
+
+/// Newest first, filtered by user and status if given
+pub async fn get_payouts(pool: &SqlitePool, user: Option<u32>, status: Option<PayoutStatus>) -> Result<Vec<PayoutRow>, DatabaseError> {
+    let payouts = sqlx::query_as(
+        r#"
+        SELECT * FROM Payout
+        WHERE (?1 IS NULL OR user = ?1) AND (?2 IS NULL OR status = ?2)
+        ORDER BY requested_at DESC, id DESC
+        "#
+    ).bind(user).bind(status).fetch_all(pool).await?;
+    Ok(payouts)
+}
+
+/// The user's payout that is requested or approved but not yet paid, if any
+pub async fn get_open_payout(pool: &SqlitePool, user: u32) -> Result<Option<PayoutRow>, DatabaseError> {
+    let payout = sqlx::query_as(
+        r#"
+        SELECT * FROM Payout WHERE user = ? AND status IN ('requested', 'approved')
+        "#
+    ).bind(user).fetch_optional(pool).await?;
+    Ok(payout)
+}
+
+/// Moves the payout from `from` to `to`, recording who approved or paid it. Returns false if it was no longer in `from`
+pub async fn update_payout_status(pool: &SqlitePool, id: u32, from: PayoutStatus, to: PayoutStatus, by: u32, note: Option<String>) -> Result<bool, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE Payout SET
+            status = ?1,
+            approved_by = CASE WHEN ?1 = 'approved' THEN ?2 ELSE approved_by END,
+            approved_at = CASE WHEN ?1 = 'approved' THEN ?3 ELSE approved_at END,
+            paid_by = CASE WHEN ?1 = 'paid' THEN ?2 ELSE paid_by END,
+            paid_at = CASE WHEN ?1 = 'paid' THEN ?3 ELSE paid_at END,
+            note = COALESCE(?4, note)
+        WHERE id = ?5 AND status = ?6
+        "#
+    ).bind(to)
+    .bind(by)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .bind(note)
+    .bind(id)
+    .bind(from)
+    .execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Payments with one of `references`, together with all payments paid within `start..end`
+pub async fn get_settlement_payments(pool: &SqlitePool, references: &[String], start: i64, end: i64) -> Result<Vec<SettlementPaymentRow>, DatabaseError> {
+    let mut builder = QueryBuilder::new(r#"
+        SELECT spr.id, spr.user, u.email AS user_email, spr.amount, spr.status, spr.payment_reference, spr.paid_at,
+            COALESCE((SELECT SUM(st.amount) FROM StoreTransaction st
+                WHERE st.payment_reference = spr.id AND st.kind = 'deposit'), 0.0) AS credited
+        FROM SwishPaymentRequest spr
+        JOIN User u ON u.id = spr.user
+        WHERE spr.payment_reference IS NOT NULL
+        "#);
+    builder.push(" AND ((spr.status = 'paid' AND spr.paid_at >= ").push_bind(start);
+    builder.push(" AND spr.paid_at < ").push_bind(end).push(")");
+
+    if !references.is_empty() {
+        builder.push(" OR spr.payment_reference IN (");
+        let mut sep = builder.separated(", ");
+        for reference in references {
+            sep.push_bind(reference);
+        }
+        builder.push(")");
+    }
+    builder.push(")");
+
+    let payments = builder.build_query_as().fetch_all(pool).await?;
+    Ok(payments)
+}
+
+pub async fn set_payment_reference(pool: &SqlitePool, payment_id: String, payment_reference: String) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        UPDATE SwishPaymentRequest
+        SET payment_reference = ?
+        WHERE id = ?
+        "#
+    ).bind(payment_reference).bind(payment_id).execute(pool).await?;
+    Ok(())
+}
+
+/// Ledger entries created for a payment, e.g. the deposit and its bonus
+pub async fn get_payment_transactions(pool: &SqlitePool, payment_id: &str) -> Result<Vec<TransactionRow>, DatabaseError> {
+    let transactions: Vec<TransactionRow> = sqlx::query_as(
+        r#"
+        SELECT id, user, amount, datetime, admin_issued, price_tier, kind, payment_reference, note, payment_method
+        FROM StoreTransaction
+        WHERE payment_reference = ?
+        ORDER BY id ASC
+        "#
+    ).bind(payment_id).fetch_all(pool).await?;
+    Ok(transactions)
+}
+
+pub async fn create_refund(pool: &SqlitePool, row: SwishRefundRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO SwishRefund (id, payment_request, user, amount, bonus_reversed, status, callback_identifier, location, issued_by, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#
+    ).bind(row.id)
+    .bind(row.payment_request)
+    .bind(row.user)
+    .bind(row.amount)
+    .bind(row.bonus_reversed)
     .bind(row.status)
-    .bind(row.token)
     .bind(row.callback_identifier)
     .bind(row.location)
+    .bind(row.issued_by)
+    .bind(row.created_at)
     .execute(pool).await?;
 
     Ok(())
 }
 
-pub async fn get_payment_request(pool: &SqlitePool, payment_id: String) -> Result<SwishPaymentRequestRow, DatabaseError> {
-    let payment_request = sqlx::query_as(
+pub async fn get_refund(pool: &SqlitePool, refund_id: String) -> Result<SwishRefundRow, DatabaseError> {
+    let refund = sqlx::query_as(
         r#"
-        SELECT * FROM SwishPaymentRequest WHERE id = ?
+        SELECT * FROM SwishRefund WHERE id = ?
         "#
-    ).bind(payment_id).fetch_one(pool).await?;
-    Ok(payment_request)
+    ).bind(refund_id).fetch_one(pool).await?;
+    Ok(refund)
 }
 
-pub async fn update_payment_request(pool: &SqlitePool, payment_id: String, status: swish::Status) -> Result<(), DatabaseError> {
-    sqlx::query(
+pub async fn get_refunds(pool: &SqlitePool, payment_id: String) -> Result<Vec<SwishRefundRow>, DatabaseError> {
+    let refunds = sqlx::query_as(
         r#"
-        UPDATE SwishPaymentRequest
+        SELECT * FROM SwishRefund WHERE payment_request = ? ORDER BY created_at ASC
+        "#
+    ).bind(payment_id).fetch_all(pool).await?;
+    Ok(refunds)
+}
+
+/// Moves the refund from `from` to `to`, false if its status was no longer `from`
+pub async fn update_refund_status(pool: &SqlitePool, refund_id: String, from: swish::RefundStatus, to: swish::RefundStatus) -> Result<bool, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE SwishRefund
         SET status = ?
-        WHERE id = ?
+        WHERE id = ? AND status = ?
+        "#
+    ).bind(to).bind(refund_id).bind(from).execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn create_callback_log(pool: &SqlitePool, row: SwishCallbackLogRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO SwishCallbackLog (kind, reference, source_ip, payload, rejection, received_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#
+    ).bind(row.kind)
+    .bind(row.reference)
+    .bind(row.source_ip)
+    .bind(row.payload)
+    .bind(row.rejection)
+    .bind(row.received_at)
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// Newest first
+/// Removes callbacks received before `before`, returns how many were removed
+pub async fn prune_callback_log(pool: &SqlitePool, before: i64) -> Result<u64, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM SwishCallbackLog WHERE received_at < ?
         "#
-    ).bind(status).bind(payment_id).execute(pool).await?;
+    ).bind(before).execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn get_callback_log(pool: &SqlitePool, rejected_only: bool, limit: u32) -> Result<Vec<SwishCallbackLogRow>, DatabaseError> {
+    let rows = sqlx::query_as(
+        r#"
+        SELECT id, kind, reference, source_ip, payload, rejection, received_at
+        FROM SwishCallbackLog
+        WHERE (? = 0 OR rejection IS NOT NULL)
+        ORDER BY received_at DESC, id DESC
+        LIMIT ?
+        "#
+    ).bind(rejected_only).bind(limit).fetch_all(pool).await?;
+    Ok(rows)
+}
+
+pub async fn get_spending_limit(pool: &SqlitePool, user: u32) -> Result<Option<SpendingLimitRow>, DatabaseError> {
+    let limit = sqlx::query_as(
+        r#"
+        SELECT * FROM SpendingLimit WHERE user = ?
+        "#
+    ).bind(user).fetch_optional(pool).await?;
+    Ok(limit)
+}
+
+pub async fn set_spending_limit(pool: &SqlitePool, limit: &SpendingLimitRow) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO SpendingLimit (user, daily, weekly, cooldown_days, pending_daily, pending_weekly, pending_cooldown_days, pending_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(user) DO UPDATE SET
+            daily = excluded.daily,
+            weekly = excluded.weekly,
+            cooldown_days = excluded.cooldown_days,
+            pending_daily = excluded.pending_daily,
+            pending_weekly = excluded.pending_weekly,
+            pending_cooldown_days = excluded.pending_cooldown_days,
+            pending_at = excluded.pending_at,
+            updated_at = excluded.updated_at
+        "#
+    ).bind(limit.user)
+    .bind(limit.daily)
+    .bind(limit.weekly)
+    .bind(limit.cooldown_days)
+    .bind(limit.pending_daily)
+    .bind(limit.pending_weekly)
+    .bind(limit.pending_cooldown_days)
+    .bind(limit.pending_at)
+    .bind(limit.updated_at)
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// Logs a purchase against the user's spending limit and prunes entries too old to count towards it
+pub async fn add_spending(pool: &SqlitePool, user: u32, amount: f32) -> Result<(), DatabaseError> {
+    let now = UtcDateTime::now().unix_timestamp();
+    sqlx::query(
+        r#"
+        INSERT INTO SpendingEntry (user, amount, datetime)
+        VALUES (?, ?, ?)
+        "#
+    ).bind(user)
+    .bind(amount)
+    .bind(now)
+    .execute(pool).await?;
+    sqlx::query(
+        r#"
+        DELETE FROM SpendingEntry WHERE user = ? AND datetime < ?
+        "#
+    ).bind(user)
+    .bind(now - 8 * 86400) // A week and a day covers the weekly cap in any timezone
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// Gives back the budget of an undone purchase. The entry is logged right after the transaction,
+/// so it is matched on amount within a second of the transaction time
+pub async fn remove_spending(pool: &SqlitePool, user: u32, amount: f32, datetime: i64) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM SpendingEntry WHERE id = (
+            SELECT id FROM SpendingEntry
+            WHERE user = ? AND amount = ? AND datetime BETWEEN ? AND ?
+            ORDER BY id DESC LIMIT 1
+        )
+        "#
+    ).bind(user)
+    .bind(amount)
+    .bind(datetime)
+    .bind(datetime + 1)
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// Total spent on purchases by the user since `since`
+pub async fn get_spending_since(pool: &SqlitePool, user: u32, since: i64) -> Result<f32, DatabaseError> {
+    let spent = sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(SUM(amount), 0.0) FROM SpendingEntry WHERE user = ? AND datetime >= ?
+        "#
+    ).bind(user).bind(since).fetch_one(pool).await?;
+    Ok(spent)
+}
+
+/// Users opted in to monthly statements who have not been sent one for `month` (YYYY-MM)
+pub async fn get_statement_recipients(pool: &SqlitePool, month: &str) -> Result<Vec<UserRow>, DatabaseError> {
+    let users = sqlx::query_as(
+        r#"
+        SELECT id, name, email, google_id, role, balance, on_leaderboard, private_transactions, is_member,
+            low_balance_alerts, low_balance_threshold, email_receipts, monthly_statements
+        FROM User
+        WHERE monthly_statements = 1
+            AND NOT EXISTS (SELECT 1 FROM MonthlyStatement ms WHERE ms.user = User.id AND ms.month = ?)
+        "#
+    ).bind(month).fetch_all(pool).await?;
+    Ok(users)
+}
+
+pub async fn mark_statement_sent(pool: &SqlitePool, user_id: u32, month: &str) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO MonthlyStatement (user, month, sent_at) VALUES (?, ?, ?)
+        "#
+    ).bind(user_id).bind(month).bind(UtcDateTime::now().unix_timestamp())
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// The transactions linked to the user between `start` and `end`, oldest first
+pub async fn get_user_transactions_between(pool: &SqlitePool, user_id: u32, start: i64, end: i64) -> Result<Vec<TransactionRow>, DatabaseError> {
+    let transactions = sqlx::query_as(
+        r#"
+        SELECT id, user, amount, datetime, admin_issued, price_tier, kind, payment_reference, note, payment_method
+        FROM StoreTransaction
+        WHERE user = ? AND datetime >= ? AND datetime < ?
+        ORDER BY datetime, id
+        "#
+    ).bind(user_id).bind(start).bind(end).fetch_all(pool).await?;
+    Ok(transactions)
+}
+
+pub async fn create_notification(pool: &SqlitePool, user: u32, kind: NotificationKind, message: &str, transaction_id: Option<u32>) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT INTO Notification (user, kind, message, transaction_id, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#
+    ).bind(user)
+    .bind(kind)
+    .bind(message)
+    .bind(transaction_id)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .execute(pool).await?;
+    Ok(())
+}
+
+/// Notifies everyone having `product` as a favourite, returns how many were notified
+pub async fn notify_favourite_restocked(pool: &SqlitePool, product: u32, message: &str) -> Result<u64, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO Notification (user, kind, message, product, created_at)
+        SELECT user, 'restock', ?, product, ? FROM FavouriteProduct WHERE product = ?
+        "#
+    ).bind(message)
+    .bind(UtcDateTime::now().unix_timestamp())
+    .bind(product)
+    .execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+/// Newest first
+pub async fn get_notifications(pool: &SqlitePool, user: u32, unread_only: bool, limit: u32, offset: u32) -> Result<Vec<NotificationRow>, DatabaseError> {
+    let notifications = sqlx::query_as(
+        r#"
+        SELECT id, kind, message, transaction_id, product, created_at, read_at
+        FROM Notification
+        WHERE user = ? AND (? = 0 OR read_at IS NULL)
+        ORDER BY created_at DESC, id DESC
+        LIMIT ? OFFSET ?
+        "#
+    ).bind(user).bind(unread_only).bind(limit).bind(offset).fetch_all(pool).await?;
+    Ok(notifications)
+}
+
+pub async fn count_unread_notifications(pool: &SqlitePool, user: u32) -> Result<u32, DatabaseError> {
+    let count = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM Notification WHERE user = ? AND read_at IS NULL
+        "#
+    ).bind(user).fetch_one(pool).await?;
+    Ok(count)
+}
+
+/// Marks the given notifications of the user as read, or all of them if `ids` is None
+pub async fn mark_notifications_read(pool: &SqlitePool, user: u32, ids: Option<&[u32]>) -> Result<u64, DatabaseError> {
+    if ids.is_some_and(|ids| ids.is_empty()) {
+        return Ok(0);
+    }
+    let mut builder = QueryBuilder::new("UPDATE Notification SET read_at = ");
+    builder.push_bind(UtcDateTime::now().unix_timestamp());
+    builder.push(" WHERE read_at IS NULL AND user = ").push_bind(user);
+    if let Some(ids) = ids {
+        builder.push(" AND id IN (");
+        let mut sep = builder.separated(", ");
+        for id in ids {
+            sep.push_bind(*id);
+        }
+        builder.push(")");
+    }
+
+    let result = builder.build().execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn add_favourite(pool: &SqlitePool, user: u32, product: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO FavouriteProduct (user, product, created_at) VALUES (?, ?, ?)
+        "#
+    ).bind(user).bind(product).bind(UtcDateTime::now().unix_timestamp())
+    .execute(pool).await?;
+    Ok(())
+}
+
+pub async fn remove_favourite(pool: &SqlitePool, user: u32, product: u32) -> Result<(), DatabaseError> {
+    sqlx::query(
+        r#"
+        DELETE FROM FavouriteProduct WHERE user = ? AND product = ?
+        "#
+    ).bind(user).bind(product).execute(pool).await?;
     Ok(())
 }
+
+/// Product ids, in the order they were added
+pub async fn get_favourites(pool: &SqlitePool, user: u32) -> Result<Vec<u32>, DatabaseError> {
+    let products = sqlx::query_scalar(
+        r#"
+        SELECT product FROM FavouriteProduct WHERE user = ? ORDER BY created_at, product
+        "#
+    ).bind(user).fetch_all(pool).await?;
+    Ok(products)
+}
+
+pub async fn get_quick_carts(pool: &SqlitePool, user: u32) -> Result<Vec<QuickCartRow>, DatabaseError> {
+    let carts = sqlx::query_as(
+        r#"
+        SELECT id, name, cart, created_at, updated_at FROM QuickCart WHERE user = ? ORDER BY name
+        "#
+    ).bind(user).fetch_all(pool).await?;
+    Ok(carts)
+}
+
+pub async fn get_quick_cart(pool: &SqlitePool, user: u32, id: u32) -> Result<Option<QuickCartRow>, DatabaseError> {
+    let cart = sqlx::query_as(
+        r#"
+        SELECT id, name, cart, created_at, updated_at FROM QuickCart WHERE user = ? AND id = ?
+        "#
+    ).bind(user).bind(id).fetch_optional(pool).await?;
+    Ok(cart)
+}
+
+/// Replaces the contents of the user's cart with the same name if there is one
+pub async fn save_quick_cart(pool: &SqlitePool, user: u32, name: &str, cart: &Cart) -> Result<QuickCartRow, DatabaseError> {
+    let now = UtcDateTime::now().unix_timestamp();
+    let cart = sqlx::query_as(
+        r#"
+        INSERT INTO QuickCart (user, name, cart, created_at, updated_at) VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(user, name) DO UPDATE SET cart = excluded.cart, updated_at = excluded.updated_at
+        RETURNING id, name, cart, created_at, updated_at
+        "#
+    ).bind(user).bind(name).bind(sqlx::types::Json(cart)).bind(now).bind(now)
+    .fetch_one(pool).await?;
+    Ok(cart)
+}
+
+pub async fn count_quick_carts(pool: &SqlitePool, user: u32) -> Result<u32, DatabaseError> {
+    let count = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM QuickCart WHERE user = ?
+        "#
+    ).bind(user).fetch_one(pool).await?;
+    Ok(count)
+}
+
+/// Returns false if the user has no such cart
+pub async fn delete_quick_cart(pool: &SqlitePool, user: u32, id: u32) -> Result<bool, DatabaseError> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM QuickCart WHERE user = ? AND id = ?
+        "#
+    ).bind(user).bind(id).execute(pool).await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The user's latest purchase linked to them, private purchases are not found
+pub async fn get_last_purchase(pool: &SqlitePool, user: u32) -> Result<Option<u32>, DatabaseError> {
+    let transaction_id = sqlx::query_scalar(
+        r#"
+        SELECT id FROM StoreTransaction WHERE user = ? AND kind = 'purchase' ORDER BY datetime DESC, id DESC LIMIT 1
+        "#
+    ).bind(user).fetch_optional(pool).await?;
+    Ok(transaction_id)
+}
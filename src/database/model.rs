@@ -1,4 +1,6 @@
-use crate::{Role, model::ProductFlags, routes::payment::swish};
+use time::OffsetDateTime;
+
+use crate::{Role, model::{AllergenFlags, Availability, DepositBonus, Discount, ProductTarget, Nutrition, NotificationKind, PayoutStatus, PriceTier, ProductFlags, TransactionKind}, routes::{payment::{PaymentMethod, swish}, products::Cart}};
 
 /// DO NOT SEND TO FRONTEND
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
@@ -10,7 +12,12 @@ pub struct UserRow {
     pub balance: f32,
     pub role: Role,
     pub on_leaderboard: bool,
-    pub private_transactions: bool
+    pub private_transactions: bool,
+    pub is_member: bool,
+    pub low_balance_alerts: bool,
+    pub low_balance_threshold: f32,
+    pub email_receipts: bool,
+    pub monthly_statements: bool,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
@@ -19,18 +26,55 @@ pub struct ProductRow {
     pub name: String,
     pub price: f32,
     pub description: String,
+    pub category: Option<String>,
     pub stock: Option<i32>,
     pub flags: sqlx::types::Json<ProductFlags>,
+    pub allergens: sqlx::types::Json<AllergenFlags>,
+    pub nutrition: Option<sqlx::types::Json<Nutrition>>,
+    pub ingredients: Option<String>,
+    pub release_at: Option<i64>,
+    pub availability: Option<sqlx::types::Json<Availability>>,
+    pub new_since: Option<i64>, // When new_product was last turned on
+}
+
+impl ProductRow {
+    /// Released and inside its availability window. `local_time` must be in the store's time zone
+    pub fn is_available_at(&self, local_time: OffsetDateTime) -> bool {
+        let released = self.release_at.is_none_or(|release_at| release_at <= local_time.unix_timestamp());
+        let open = self.availability.as_ref().is_none_or(|a| a.is_open(local_time));
+        released && open
+    }
 }
 
 
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct ProductPriceRow {
+    pub id: u32,
+    pub product: u32,
+    pub price: f32,
+    pub effective_from: i64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct ProductPriceTierRow {
+    pub product: u32,
+    pub tier: PriceTier,
+    pub price: f32,
+}
+
 #[derive(Debug, sqlx::FromRow, serde::Serialize)]
 pub struct TransactionRow {
     pub id: u32,
     pub user: u32,
     pub amount: f32,
     pub admin_issued: bool,
-    pub datetime: i64
+    pub datetime: i64,
+    pub price_tier: Option<PriceTier>,
+    pub kind: TransactionKind,
+    pub payment_reference: Option<String>,
+    pub note: Option<String>,
+    pub payment_method: Option<PaymentMethod>,
 }
 
 #[derive(sqlx::FromRow)]
@@ -41,6 +85,102 @@ pub struct TransactionItemRow {
     pub quantity: u32,
     pub name: String,
     pub price: f32,
+    pub discount: f32, // Per unit
+    pub discount_rule: Option<u32>,
+    pub bundle: Option<u32>,
+    pub price_tier: Option<PriceTier>,
+    pub stamp_program: Option<u32>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct BundleRow {
+    pub id: u32,
+    pub name: String,
+    pub price: f32,
+    pub auto_apply: bool,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct BundleComponentRow {
+    #[serde(skip_deserializing)]
+    pub bundle: u32,
+    pub product: u32,
+    pub quantity: u32,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct StampCardProgramRow {
+    pub id: u32,
+    pub name: String,
+    pub target: sqlx::types::Json<ProductTarget>,
+    pub stamps_required: u32,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct DiscountRuleRow {
+    pub id: u32,
+    pub name: String,
+    pub target: sqlx::types::Json<ProductTarget>,
+    pub discount: sqlx::types::Json<Discount>,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    pub time_window: Option<sqlx::types::Json<Availability>>,
+    pub active: bool,
+}
+
+impl DiscountRuleRow {
+    /// `local_time` must be in the store's time zone
+    pub fn applies_to(&self, product: &ProductRow, local_time: OffsetDateTime) -> bool {
+        let now = local_time.unix_timestamp();
+        let in_period = self.starts_at.is_none_or(|start| start <= now) && self.ends_at.is_none_or(|end| now < end);
+        let in_window = self.time_window.as_ref().is_none_or(|w| w.is_open(local_time));
+        self.active && in_period && in_window && self.target.matches(product)
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct DepositBonusRuleRow {
+    pub id: u32,
+    pub name: String,
+    pub bonus: sqlx::types::Json<DepositBonus>,
+    pub min_amount: f32,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    pub active: bool,
+}
+
+impl DepositBonusRuleRow {
+    /// Bonus for depositing `amount` at UNIX timestamp `now`, zero if the rule does not apply
+    pub fn bonus_for(&self, amount: f32, now: i64) -> f32 {
+        let in_period = self.starts_at.is_none_or(|start| start <= now) && self.ends_at.is_none_or(|end| now < end);
+        if !self.active || !in_period || amount < self.min_amount {
+            return 0.0;
+        }
+        self.bonus.amount(amount)
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct VoucherRow {
+    pub id: u32,
+    pub code: String,
+    pub value: f32,
+    pub max_redemptions: u32,
+    pub expires_at: Option<i64>,
+    pub note: Option<String>,
+    pub created_by: Option<u32>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct VoucherRedemptionRow {
+    pub voucher: u32,
+    pub user: Option<u32>,
+    pub user_email: Option<String>, // None if the user has been deleted
+    pub transaction_id: Option<u32>,
+    pub redeemed_at: i64,
 }
 
 #[derive(sqlx::FromRow)]
@@ -52,4 +192,175 @@ pub struct SwishPaymentRequestRow {
     pub token: String,
     pub callback_identifier: String, // Ensure Swish's POST callback is legit
     pub location: String, // Where to poll Swish for new status
+    pub payment_reference: Option<String>, // Swish's reference, needed for refunds
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub paid_at: Option<i64>,
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+}
+
+/// A payment request as shown in payment history
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct SwishPaymentSummaryRow {
+    pub id: String,
+    pub user: u32,
+    pub user_email: String,
+    pub amount: f32,
+    pub refunded: f32, // Sum of refunds that have not failed
+    pub status: swish::Status,
+    pub payment_reference: Option<String>,
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub paid_at: Option<i64>,
+}
+
+/// A Swish payment as compared against a settlement report
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
+pub struct SettlementPaymentRow {
+    pub id: String,
+    pub user: u32,
+    pub user_email: String,
+    pub amount: f32,
+    pub status: swish::Status,
+    pub payment_reference: String,
+    pub paid_at: Option<i64>,
+    pub credited: f32, // Sum of deposit transactions referencing the payment request
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct CashDepositRow {
+    pub id: u32,
+    pub user: Option<u32>, // None if the user has been deleted
+    pub amount: f32,
+    pub refunded: f32,
+    pub received_by: Option<u32>,
+    pub created_at: i64,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct CashWithdrawalRow {
+    pub id: u32,
+    pub amount: f32,
+    pub note: String,
+    pub created_by: Option<u32>,
+    pub created_at: i64,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Clone)]
+pub struct CashCountRow {
+    pub id: u32,
+    pub counted: f32,
+    pub expected: f32,
+    pub note: Option<String>,
+    pub counted_by: Option<u32>,
+    pub created_at: i64,
+    #[serde(skip)]
+    pub transaction_id: u32,
+    #[serde(skip)]
+    pub withdrawal_id: u32,
+}
+
+/// Cash that went in and out of the box during a period
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct CashFlowRow {
+    pub deposits: f32,
+    pub refunds: f32, // Handed back to users, positive
+    pub withdrawals: f32,
+}
+
+impl CashFlowRow {
+    pub fn net(&self) -> f32 {
+        self.deposits - self.refunds - self.withdrawals
+    }
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct PaymentMethodTotalRow {
+    pub method: Option<PaymentMethod>, // None for entries made outside a payment provider
+    pub count: u32,
+    pub total: f32,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct SalesRow {
+    pub count: u32,
+    pub total: f32,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct PayoutRow {
+    pub id: u32,
+    pub user: Option<u32>, // None if the user has been deleted
+    pub amount: f32,
+    pub method: PaymentMethod,
+    pub destination: Option<String>,
+    pub status: PayoutStatus,
+    pub note: Option<String>,
+    pub requested_at: i64,
+    pub approved_by: Option<u32>,
+    pub approved_at: Option<i64>,
+    pub paid_by: Option<u32>,
+    pub paid_at: Option<i64>,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct QuickCartRow {
+    pub id: u32,
+    pub name: String,
+    pub cart: sqlx::types::Json<Cart>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct NotificationRow {
+    pub id: u32,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub transaction_id: Option<u32>,
+    pub product: Option<u32>,
+    pub created_at: i64,
+    pub read_at: Option<i64>, // None while unread
+}
+
+#[derive(sqlx::FromRow, Clone)]
+pub struct SpendingLimitRow {
+    pub user: u32,
+    pub daily: Option<f32>,
+    pub weekly: Option<f32>,
+    pub cooldown_days: u32,
+    pub pending_daily: Option<f32>,
+    pub pending_weekly: Option<f32>,
+    pub pending_cooldown_days: Option<u32>,
+    pub pending_at: Option<i64>, // None if there is no pending change
+    pub updated_at: i64,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct SwishRefundRow {
+    pub id: String, // UUID as readable string
+    pub payment_request: String,
+    pub user: Option<u32>,
+    pub amount: f32,
+    pub bonus_reversed: f32,
+    pub status: swish::RefundStatus,
+    #[serde(skip)]
+    pub callback_identifier: String,
+    pub location: Option<String>,
+    pub issued_by: Option<u32>,
+    pub created_at: i64,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+pub struct SwishCallbackLogRow {
+    pub id: u32,
+    pub kind: swish::CallbackKind,
+    pub reference: Option<String>,
+    pub source_ip: Option<String>,
+    pub payload: String,
+    pub rejection: Option<String>, // None if accepted
+    pub received_at: i64,
 }
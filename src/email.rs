@@ -0,0 +1,194 @@
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::{Mailbox, header::ContentType}, transport::smtp::authentication::Credentials};
+use time::{Date, OffsetDateTime, macros::format_description};
+use time_tz::{OffsetDateTimeExt, Tz};
+
+use crate::{EnvironmentVariables, database::model::TransactionRow, error::GenericError, model::TransactionKind};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    /// TLS from the start, usually port 465
+    Tls,
+    /// Upgraded with STARTTLS, usually port 587
+    StartTls,
+    /// Plain text, only for a local SMTP catcher
+    None,
+}
+
+impl SmtpSecurity {
+    pub fn parse(string: &str) -> Option<SmtpSecurity> {
+        match string.to_lowercase().as_str() {
+            "tls" => Some(SmtpSecurity::Tls),
+            "starttls" => Some(SmtpSecurity::StartTls),
+            "none" => Some(SmtpSecurity::None),
+            _ => None
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::StartTls => 587,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, e.g. `Konsfekt <noreply@example.com>`
+    pub from: String,
+}
+
+/// Sends emails over the configured SMTP server. Without one every email is dropped with a debug log,
+/// so callers do not need to check whether email is set up
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Option<(AsyncSmtpTransport<Tokio1Executor>, Mailbox)>,
+}
+
+impl Mailer {
+    pub fn new(env: &EnvironmentVariables) -> Result<Self, GenericError> {
+        let Some(config) = &env.smtp else {
+            log::info!("SMTP_HOST not set, no emails will be sent");
+            return Ok(Mailer { transport: None });
+        };
+
+        let from: Mailbox = config.from.parse()
+            .map_err(|err| GenericError::new("Could not parse EMAIL_FROM").add_info(format!("{}: {err}", config.from)))?;
+        let builder = match config.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)),
+        }.map_err(|err| GenericError::new("Could not set up SMTP transport").add_info(err.to_string()))?;
+        let builder = builder.port(config.port);
+        let builder = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => builder.credentials(Credentials::new(username.clone(), password.clone())),
+            _ => builder,
+        };
+
+        if config.security == SmtpSecurity::None {
+            log::warn!("Sending emails without TLS to {}:{}", config.host, config.port);
+        }
+        Ok(Mailer { transport: Some((builder.build(), from)) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Sends a plain text email
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), GenericError> {
+        let Some((transport, from)) = &self.transport else {
+            log::debug!("Email \"{subject}\" to {to} not sent, SMTP is not configured");
+            return Ok(());
+        };
+        let to: Mailbox = to.parse()
+            .map_err(|err| GenericError::new("Invalid recipient address").add_info(format!("{to}: {err}")))?;
+        let message = Message::builder()
+            .from(from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|err| GenericError::new("Could not build email").add_info(err.to_string()))?;
+        transport.send(message).await
+            .map_err(|err| GenericError::new("Could not send email").add_info(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Sends without waiting for the SMTP server, failures are only logged
+    pub fn send_in_background(&self, to: String, subject: String, body: String) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = mailer.send(&to, &subject, body).await {
+                log::warn!("Could not email {to}: {err}");
+            }
+        });
+    }
+}
+
+//
+//          Email contents
+//
+
+pub struct ReceiptLine {
+    pub name: String,
+    pub quantity: u32,
+    pub total: f32,
+}
+
+fn format_datetime(timestamp: i64, timezone: &Tz) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map(|datetime| datetime.to_timezone(timezone))
+        .ok()
+        .and_then(|datetime| datetime.format(format_description!("[year]-[month]-[day] [hour]:[minute]")).ok())
+        .unwrap_or_default()
+}
+
+pub fn receipt(transaction_id: u32, lines: &[ReceiptLine], total: f32, balance: f32) -> (String, String) {
+    let mut body = format!("Thank you for your purchase!\n\nReceipt #{transaction_id}\n\n");
+    for line in lines {
+        body += &format!("{} x {:<30} {:>8.2} kr\n", line.quantity, line.name, line.total);
+    }
+    body += &format!("\nTotal: {total:.2} kr\nBalance: {balance:.2} kr\n");
+    (format!("Konsfekt receipt #{transaction_id}"), body)
+}
+
+pub fn low_balance(balance: f32, threshold: f32, site_domain: &str) -> (String, String) {
+    let body = format!(
+        "Your Konsfekt balance is {balance:.2} kr, below your alert level of {threshold:.2} kr.\n\n\
+        Top up at {site_domain} to keep buying.\n\n\
+        You can turn these alerts off in your settings."
+    );
+    (String::from("Your Konsfekt balance is running low"), body)
+}
+
+fn kind_label(kind: TransactionKind) -> &'static str {
+    match kind {
+        TransactionKind::Purchase => "Purchase",
+        TransactionKind::Deposit => "Deposit",
+        TransactionKind::Bonus => "Deposit bonus",
+        TransactionKind::Refund => "Refund",
+        TransactionKind::Adjustment => "Adjustment",
+        TransactionKind::Voucher => "Voucher",
+        TransactionKind::Payout => "Payout",
+    }
+}
+
+/// Statement of the transactions linked to the user during `month`, private purchases are not linked and thus not listed
+pub fn monthly_statement(month: Date, transactions: &[TransactionRow], balance: f32, private_transactions: bool, timezone: &Tz) -> (String, String) {
+    let month_name = month.format(format_description!("[year]-[month]")).unwrap_or_default();
+    let mut body = format!("Your Konsfekt statement for {month_name}\n\n");
+    if transactions.is_empty() {
+        body += "No transactions this month.\n";
+    }
+    for transaction in transactions {
+        body += &format!("{}  {:<14} {:>9.2} kr", format_datetime(transaction.datetime, timezone), kind_label(transaction.kind), transaction.amount);
+        if let Some(note) = &transaction.note {
+            body += &format!("  {note}");
+        }
+        body += "\n";
+    }
+
+    let sum_of = |kinds: &[TransactionKind]| -> f32 {
+        transactions.iter().filter(|t| kinds.contains(&t.kind)).fold(0.0, |sum, t| sum + t.amount)
+    };
+    body += &format!(
+        "\nDeposits: {:.2} kr\nPurchases: {:.2} kr\nOther: {:.2} kr\nCurrent balance: {balance:.2} kr\n",
+        sum_of(&[TransactionKind::Deposit, TransactionKind::Bonus, TransactionKind::Voucher]),
+        -sum_of(&[TransactionKind::Purchase]),
+        sum_of(&[TransactionKind::Refund, TransactionKind::Adjustment, TransactionKind::Payout]),
+    );
+    if private_transactions {
+        body += "\nPurchases made with private transactions turned on are not listed.\n";
+    }
+    (format!("Konsfekt statement {month_name}"), body)
+}
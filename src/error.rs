@@ -135,8 +135,8 @@ pub struct SwishErrorResponse {
     code: String,
     #[serde(rename = "errorMessage")]
     message: String,
-    #[serde(rename = "additionalInformation")]
-    additional_info: String,
+    #[serde(rename = "additionalInformation", default)]
+    additional_info: String, // Often left out by Swish
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -158,10 +158,9 @@ impl fmt::Display for SwishErrorList {
             _ => {
                 let mut err_str = format!("Swish error with status {status_str}");
                 for swish_response in &self.list {
-                    err_str.push_str(&format!("\n\tSwish error {}: {}. Additional info: {}", 
+                    err_str.push_str(&format!("\n\tSwish error {}: {}", 
                         swish_response.code, 
-                        swish_response.message, 
-                        swish_response.additional_info));
+                        swish_response.message));
                     if !swish_response.additional_info.is_empty() {
                         err_str.push_str(&format!("\n\t\tAdditional info: {}", swish_response.additional_info));
                     }
@@ -173,15 +172,38 @@ impl fmt::Display for SwishErrorList {
 }
 
 impl SwishErrorResponse {
+    /// Rejections of the request's content (e.g. refunding more than was paid) keep their status,
+    /// anything else is on our side or Swish's and becomes an internal server error
     pub async fn to_error(resp: reqwest::Response) -> AppError {
         let status = resp.status();
+        let http_code = match status.as_u16() {
+            409 => StatusCode::CONFLICT,
+            422 => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
         match resp.json::<Vec<SwishErrorResponse>>().await {
             Ok(list) => {
-                SwishError::from(SwishErrorList { list, http_status: Some(status) }).into()
+                SwishError::from(SwishErrorList { list, http_status: Some(status) }).with_status(http_code).into()
             },
             Err(err) => ClientError::from(err).into()
         }
     }
+
+    /// Human readable explanation of refund error codes sent in refund callbacks
+    pub fn describe_refund_error(code: &str) -> &'static str {
+        match code {
+            "RF02" => "Original payment not found or more than 13 months old",
+            "RF03" => "Payer alias does not match the original payment's payee",
+            "RF04" => "Payer organisation number does not match the original payment",
+            "RF06" => "Payer SSN does not match the original payment",
+            "RF07" => "Transaction declined",
+            "RF08" => "Amount exceeds the original payment minus earlier refunds",
+            "RF09" => "Refund already in progress",
+            "FF10" => "Bank system processing error",
+            "BE18" => "Payer alias is invalid",
+            _ => "Unknown refund error",
+        }
+    }
 }
 
 app_error_enum! {
@@ -209,7 +231,7 @@ macro_rules! match_error_variant {
 
 impl std::error::Error for AppError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        return match_error_variant!(self, std::error::Error::source);
+        match_error_variant!(self, std::error::Error::source)
     }
 }
 
@@ -221,10 +243,10 @@ impl fmt::Display for AppError {
 
 impl ResponseError for AppError {
     fn status_code(&self) -> StatusCode {
-        return match_error_variant!(self, ResponseError::status_code);
+        match_error_variant!(self, ResponseError::status_code)
     }
 
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        return match_error_variant!(self, ResponseError::error_response);
+        match_error_variant!(self, ResponseError::error_response)
     }
 }
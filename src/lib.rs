@@ -5,12 +5,18 @@ pub mod utils;
 pub mod model;
 pub mod error;
 pub mod args;
+pub mod tasks;
+pub mod email;
 
-use std::{collections::HashMap, env, fs};
+use std::{collections::HashMap, env, fs, str::FromStr};
 
+use ipnet::IpNet;
 use reqwest::{Certificate, Client, Identity};
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite};
+use time_tz::Tz;
+
+use crate::error::GenericError;
 
 #[derive(Clone)]
 pub struct EnvironmentVariables {
@@ -23,12 +29,58 @@ pub struct EnvironmentVariables {
     pub swish_number: String,
     pub use_swish_sandbox: bool,
     pub swish_api_url: String,
+    pub swish_refund_url: String,
+    /// Default side in pixels of rendered Swish QR codes
+    pub swish_qr_size: u32,
+    /// Set when SWISH_ENVIRONMENT is `simulator`, certificates are then not used
+    pub swish_simulator_url: Option<String>,
+    /// Addresses Swish callbacks may come from, any if empty
+    pub swish_callback_allowed_ips: Vec<IpNet>,
+    /// Reverse proxies whose `X-Forwarded-For` is trusted for the callback source address
+    pub swish_trusted_proxies: Vec<IpNet>,
+    /// Days Swish callbacks are kept in the callback log
+    pub swish_callback_log_days: i64,
+    /// PKCS#12 client certificate identifying us to Swish
+    pub swish_cert_path: String,
+    pub swish_cert_passphrase: String,
+    /// PEM root certificate(s) trusted when connecting to Swish
+    pub swish_ca_path: String,
+    /// Warn at startup when the client certificate expires within this many days
+    pub swish_cert_warning_days: i64,
+    /// Time zone used for product availability windows
+    pub timezone: &'static Tz,
+    /// Days until a product flagged as new loses the flag
+    pub new_product_days: i64,
+    /// Days of sales considered when computing popular products
+    pub popular_window_days: i64,
+    /// Number of best selling products flagged as popular
+    pub popular_product_count: u32,
+    /// Outgoing email server, emails are not sent if None
+    pub smtp: Option<email::SmtpConfig>,
 }
 
 fn required_env(name: &str) -> String {
     env::var(name).unwrap_or_else(|_| panic!("Missing required environment variable: {name}"))
 }
 
+fn optional_env<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("Could not parse environment variable: {name}")),
+        Err(_) => default
+    }
+}
+
+/// Comma-separated IPs and CIDRs, empty if not set
+fn ip_nets_env(name: &str) -> Vec<IpNet> {
+    optional_env(name, String::new())
+        .split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.parse::<IpNet>().or_else(|_| ip.parse::<std::net::IpAddr>().map(IpNet::from))
+            .unwrap_or_else(|_| panic!("Could not parse {name} entry: {ip}")))
+        .collect()
+}
+
 impl EnvironmentVariables {
 
     pub fn from_args(args: args::Args) -> Self {
@@ -39,8 +91,18 @@ impl EnvironmentVariables {
 
         let swish_environment = required_env("SWISH_ENVIRONMENT");
         let use_swish_sandbox = match swish_environment.as_str() {
-            "prod" => false, "sandbox" => true, 
-            _ => panic!("SWISH_ENVIRONMENT can only take values 'sandbox' and 'prod'")
+            "prod" => false, "sandbox" | "simulator" => true, 
+            _ => panic!("SWISH_ENVIRONMENT can only take values 'sandbox', 'prod' and 'simulator'")
+        };
+        // The simulator (src/bin/swish_simulator.rs) serves Swish's paths locally over plain HTTP
+        let swish_simulator_url = match swish_environment.as_str() {
+            "simulator" => Some(optional_env("SWISH_SIMULATOR_URL", String::from("http://127.0.0.1:8081"))),
+            _ => None
+        };
+        let swish_api_host = match (&swish_simulator_url, use_swish_sandbox) {
+            (Some(url), _) => url.clone(),
+            (None, true) => String::from("https://staging.getswish.pub.tds.tieto.com"),
+            (None, false) => String::from("https://cpc.getswish.net"),
         };
 
         EnvironmentVariables {
@@ -59,10 +121,47 @@ impl EnvironmentVariables {
             google_client_secret: required_env("GOOGLE_CLIENT_SECRET"),
             swish_number: required_env("SWISH_NUMBER"),
             use_swish_sandbox,
-            swish_api_url: match use_swish_sandbox {
-                true => String::from("https://staging.getswish.pub.tds.tieto.com/swish-cpcapi/api/v2/paymentrequests/"),
-                false => String::from("https://cpc.getswish.net/swish-cpcapi/api/v2/paymentrequests/"),
-            }
+            swish_api_url: format!("{swish_api_host}/swish-cpcapi/api/v2/paymentrequests/"),
+            swish_refund_url: format!("{swish_api_host}/swish-cpcapi/api/v2/refunds/"),
+            swish_qr_size: optional_env("SWISH_QR_SIZE", 300),
+            swish_simulator_url,
+            swish_callback_allowed_ips: ip_nets_env("SWISH_CALLBACK_ALLOWED_IPS"),
+            swish_trusted_proxies: ip_nets_env("SWISH_TRUSTED_PROXIES"),
+            swish_callback_log_days: optional_env("SWISH_CALLBACK_LOG_DAYS", 90),
+            swish_cert_path: match use_swish_sandbox {
+                true => optional_env("SWISH_CERT_PATH", String::from("certificates/sandbox/myCertificate.p12")),
+                false => required_env("SWISH_CERT_PATH"),
+            },
+            swish_cert_passphrase: match use_swish_sandbox {
+                true => optional_env("SWISH_CERT_PASSPHRASE", String::from("swish")),
+                false => required_env("SWISH_CERT_PASSPHRASE"),
+            },
+            swish_ca_path: match use_swish_sandbox {
+                // The sandbox certificate bundle doubles as its own trust root
+                true => optional_env("SWISH_CA_PATH", String::from("certificates/sandbox/myCertificate.pem")),
+                false => optional_env("SWISH_CA_PATH", String::from("certificates/prod/DigiCertGlobalRootG2.crt.pem")),
+            },
+            swish_cert_warning_days: optional_env("SWISH_CERT_WARNING_DAYS", 30),
+            timezone: {
+                let name = optional_env("TIMEZONE", String::from("Europe/Stockholm"));
+                time_tz::timezones::get_by_name(&name).unwrap_or_else(|| panic!("Unknown TIMEZONE: {name}"))
+            },
+            new_product_days: optional_env("NEW_PRODUCT_DAYS", 14),
+            popular_window_days: optional_env("POPULAR_WINDOW_DAYS", 14),
+            popular_product_count: optional_env("POPULAR_PRODUCT_COUNT", 3),
+            smtp: env::var("SMTP_HOST").ok().filter(|host| !host.is_empty()).map(|host| {
+                let security = optional_env("SMTP_SECURITY", String::from("starttls"));
+                let security = email::SmtpSecurity::parse(&security)
+                    .unwrap_or_else(|| panic!("SMTP_SECURITY can only take values 'tls', 'starttls' and 'none'"));
+                email::SmtpConfig {
+                    host,
+                    port: optional_env("SMTP_PORT", security.default_port()),
+                    security,
+                    username: env::var("SMTP_USERNAME").ok().filter(|username| !username.is_empty()),
+                    password: env::var("SMTP_PASSWORD").ok().filter(|password| !password.is_empty()),
+                    from: required_env("EMAIL_FROM"),
+                }
+            }),
         }
     }
 }
@@ -78,8 +177,8 @@ pub enum Role {
     Admin = 3,
 }
 
-impl Role {
-    pub fn from_str(string: &str) -> Role {
+impl From<&str> for Role {
+    fn from(string: &str) -> Role {
         match string.to_lowercase().as_str() {
             "user" => Role::User,
             "bot" => Role::Bot,
@@ -96,10 +195,11 @@ pub struct PermissionTable {
 }
 
 impl PermissionTable {
-    pub fn new() -> Self {
+    /// Reads `./permission_table.json`
+    pub fn load() -> Self {
         let json_str = fs::read_to_string(String::from("./permission_table.json")).expect("Could not open permission table file");
         let json: HashMap<String, Role> = serde_json::from_str(&json_str).unwrap();
-        return PermissionTable { table: json };
+        PermissionTable { table: json }
     }
 
     pub fn empty() -> Self {
@@ -128,29 +228,69 @@ pub struct AppState {
     pub client: Client,
     pub env: EnvironmentVariables,
     pub permission_table: PermissionTable,
+    pub mailer: email::Mailer,
 }
 
 impl AppState {
-    pub fn from(pool: Pool<Sqlite>, env_vars: EnvironmentVariables) -> Self {
-        if !env_vars.use_swish_sandbox {
-            unimplemented!("Need to figure out production certificates");
+    /// `client` should come from [`AppState::swish_client`]
+    pub fn from(pool: Pool<Sqlite>, env_vars: EnvironmentVariables, client: Client, mailer: email::Mailer) -> Self {
+        AppState {
+            db: pool,
+            client,
+            env: env_vars.clone(),
+            permission_table: PermissionTable::load(),
+            mailer,
         }
+    }
 
-        let cert_bytes = fs::read("certificates/sandbox/myCertificate.p12").unwrap();
-        let ca_cert = fs::read("certificates/sandbox/myCertificate.pem").unwrap();
+    /// HTTP client authenticating with our Swish client certificate. Fails with an explanation
+    /// if the certificates cannot be read or have expired, and warns if expiry is close
+    pub fn swish_client(env_vars: &EnvironmentVariables) -> Result<Client, GenericError> {
+        if let Some(url) = &env_vars.swish_simulator_url {
+            log::warn!("Using the Swish simulator at {url}, no real payments can be made");
+            return Ok(Client::new());
+        }
 
-        let identity = Identity::from_pkcs12_der(&cert_bytes, "swish").unwrap();
-        let ca = Certificate::from_pem(&ca_cert).unwrap();
+        let cert_bytes = fs::read(&env_vars.swish_cert_path)
+            .map_err(|err| GenericError::new("Could not read Swish client certificate (SWISH_CERT_PATH)")
+                .add_info(format!("{}: {err}", env_vars.swish_cert_path)))?;
+        let ca_cert = fs::read(&env_vars.swish_ca_path)
+            .map_err(|err| GenericError::new("Could not read Swish root certificate (SWISH_CA_PATH)")
+                .add_info(format!("{}: {err}", env_vars.swish_ca_path)))?;
 
-        AppState {
-            db: pool,
-            client: reqwest::Client::builder()
-                .identity(identity)
-                .add_root_certificate(ca)
-                .build()
-                .expect("Could not build reqwest::Client"),
-            env: env_vars.clone(),
-            permission_table: PermissionTable::new()
+        let days_left = certificate_days_left(&cert_bytes, &env_vars.swish_cert_passphrase)?;
+        if days_left < 0 {
+            return Err(GenericError::new("Swish client certificate has expired")
+                .add_info(format!("{} expired {} day(s) ago", env_vars.swish_cert_path, -days_left)));
+        }
+        if days_left <= env_vars.swish_cert_warning_days {
+            log::warn!("Swish client certificate {} expires in {days_left} day(s)", env_vars.swish_cert_path);
         }
+
+        let identity = Identity::from_pkcs12_der(&cert_bytes, &env_vars.swish_cert_passphrase)
+            .map_err(|err| GenericError::new("Could not use Swish client certificate").add_info(err.to_string()))?;
+        let ca = Certificate::from_pem(&ca_cert)
+            .map_err(|err| GenericError::new("Could not parse Swish root certificate, expected PEM").add_info(err.to_string()))?;
+
+        reqwest::Client::builder()
+            .identity(identity)
+            .add_root_certificate(ca)
+            .build()
+            .map_err(|err| GenericError::new("Could not build Swish HTTP client").add_info(err.to_string()))
     }
 }
+
+/// Days until the PKCS#12 bundle's certificate expires, negative if it already has
+fn certificate_days_left(pkcs12_der: &[u8], passphrase: &str) -> Result<i64, GenericError> {
+    let parsed = openssl::pkcs12::Pkcs12::from_der(pkcs12_der)
+        .and_then(|pkcs12| pkcs12.parse2(passphrase))
+        .map_err(|err| GenericError::new("Could not open Swish client certificate, check SWISH_CERT_PASSPHRASE")
+            .add_info(err.to_string()))?;
+    let Some(cert) = parsed.cert else {
+        return Err(GenericError::new("Swish client certificate bundle contains no certificate"));
+    };
+    let diff = openssl::asn1::Asn1Time::days_from_now(0)
+        .and_then(|now| now.diff(cert.not_after()))
+        .map_err(|err| GenericError::new("Could not read Swish client certificate expiry").add_info(err.to_string()))?;
+    Ok(diff.days as i64)
+}
@@ -1,7 +1,7 @@
 use actix_cors::Cors;
 use actix_web::{http, middleware::DefaultHeaders, web::scope};
 use clap::Parser;
-use konsfekt::{database, routes, AppState, EnvironmentVariables, args};
+use konsfekt::{database, email, routes, tasks, AppState, EnvironmentVariables, args};
 
 use actix_web::{middleware, web::Data, App, HttpServer};
 use sqlx::Sqlite;
@@ -17,6 +17,20 @@ async fn main() -> std::io::Result<()> {
     let pool = database::init_database()
         .await
         .expect("Could not initialize database");
+
+    let swish_client = AppState::swish_client(&env).unwrap_or_else(|err| {
+        log::error!("Could not set up Swish: {err}");
+        std::process::exit(1);
+    });
+
+    let mailer = email::Mailer::new(&env).unwrap_or_else(|err| {
+        log::error!("Could not set up email: {err}");
+        std::process::exit(1);
+    });
+
+    tasks::spawn_product_flag_refresh(pool.clone(), env.clone());
+    tasks::spawn_swish_polling(pool.clone(), swish_client.clone());
+    tasks::spawn_monthly_statements(pool.clone(), env.clone(), mailer.clone());
     
     if env.static_frontend {
         log::info!("Web server running at {}", env.site_domain);
@@ -26,7 +40,7 @@ async fn main() -> std::io::Result<()> {
     }
 
     let env_clone = env.clone();
-    HttpServer::new(move || create_http(env_clone.clone(), pool.clone()))
+    HttpServer::new(move || create_http(env_clone.clone(), pool.clone(), swish_client.clone(), mailer.clone()))
         .bind(("0.0.0.0", 8080))?
         .run()
         .await
@@ -49,7 +63,7 @@ fn create_logger(env: &EnvironmentVariables) {
     builder.init();
 }
 
-fn create_http(env: EnvironmentVariables, pool: sqlx::Pool<Sqlite>) -> App<impl actix_web::dev::ServiceFactory<actix_web::dev::ServiceRequest, Config = (), Response = actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, Error = actix_web::Error, InitError = ()>> {
+fn create_http(env: EnvironmentVariables, pool: sqlx::Pool<Sqlite>, swish_client: reqwest::Client, mailer: email::Mailer) -> App<impl actix_web::dev::ServiceFactory<actix_web::dev::ServiceRequest, Config = (), Response = actix_web::dev::ServiceResponse<impl actix_web::body::MessageBody>, Error = actix_web::Error, InitError = ()>> {
     let mut cors = Cors::default()
             .supports_credentials()
             .allowed_methods(vec!["GET", "POST"])
@@ -66,7 +80,7 @@ fn create_http(env: EnvironmentVariables, pool: sqlx::Pool<Sqlite>) -> App<impl
     let mut app = App::new()
         .wrap(middleware::from_fn(routes::session_middleware))
         .wrap(middleware::from_fn(routes::permission_middleware))
-        .app_data(Data::new(AppState::from(pool.clone(), env.clone())))
+        .app_data(Data::new(AppState::from(pool.clone(), env.clone(), swish_client, mailer)))
         .app_data(actix_web::web::JsonConfig::default().error_handler(|err, req| {
             log::warn!("JSON body error on {}: {}", req.path(), err);
             actix_web::error::InternalError::from_response(err, actix_web::HttpResponse::BadRequest().finish()).into()
@@ -87,6 +101,7 @@ fn create_http(env: EnvironmentVariables, pool: sqlx::Pool<Sqlite>) -> App<impl
         .service(routes::user::set_username)
         .service(routes::user::get_users)
         .service(routes::user::unlink_transactions)
+        .service(routes::user::send_test_email)
         .service(routes::user::set_user_flags)
 
         // Transaction API
@@ -98,17 +113,102 @@ fn create_http(env: EnvironmentVariables, pool: sqlx::Pool<Sqlite>) -> App<impl
         .service(routes::products::get_products)
         .service(routes::products::update_product)
         .service(routes::products::delete_product)
+        .service(routes::products::get_price_history)
+        .service(routes::products::get_price_tiers)
+        .service(routes::products::schedule_price)
+        .service(routes::products::cancel_scheduled_price)
 
         .service(routes::products::buy_products)
         .service(routes::products::buy_single_product)
         .service(routes::products::undo_transaction)
         .service(routes::products::mark_sold_out)
+
+        // Discount API
+        .service(routes::discounts::get_discount_rules)
+        .service(routes::discounts::create_discount_rule)
+        .service(routes::discounts::update_discount_rule)
+        .service(routes::discounts::delete_discount_rule)
+
+        // Bundle API
+        .service(routes::bundles::get_bundles)
+        .service(routes::bundles::create_bundle)
+        .service(routes::bundles::update_bundle)
+        .service(routes::bundles::delete_bundle)
+
+        // Stamp card API
+        .service(routes::stamps::get_stamp_cards)
+        .service(routes::stamps::get_stamp_programs)
+        .service(routes::stamps::create_stamp_program)
+        .service(routes::stamps::update_stamp_program)
+        .service(routes::stamps::delete_stamp_program)
+
+        // Deposit bonus API
+        .service(routes::bonuses::get_deposit_bonus_rules)
+        .service(routes::bonuses::create_deposit_bonus_rule)
+        .service(routes::bonuses::update_deposit_bonus_rule)
+        .service(routes::bonuses::delete_deposit_bonus_rule)
+
+        // Voucher API
+        .service(routes::vouchers::get_vouchers)
+        .service(routes::vouchers::create_vouchers)
+        .service(routes::vouchers::delete_voucher)
+        .service(routes::vouchers::redeem_voucher)
         
         // Swish API
         .service(routes::payment::swish::create_payment_request)
         .service(routes::payment::swish::swish_callback)
         .service(routes::payment::swish::check_status)
+        .service(routes::payment::swish::cancel_payment_request)
+        .service(routes::payment::swish::get_payment_history)
+        .service(routes::payment::swish::get_payment_requests)
         .service(routes::payment::swish::get_qr_code)
+        .service(routes::payment::swish::get_prefilled_qr_code)
+        .service(routes::payment::swish::refund_payment)
+        .service(routes::payment::swish::refund_callback)
+        .service(routes::payment::swish::get_refunds)
+        .service(routes::payment::swish::get_callback_log)
+        .service(routes::settlement::reconcile_settlement)
+
+        // Cash API
+        .service(routes::payment::cash::create_cash_deposit)
+        .service(routes::payment::cash::refund_cash_deposit)
+        .service(routes::payment::cash::get_cash_deposits)
+
+        // Cash box API
+        .service(routes::cash_box::get_cash_box)
+        .service(routes::cash_box::withdraw_cash)
+        .service(routes::cash_box::count_cash)
+        .service(routes::cash_box::get_cash_counts)
+        .service(routes::cash_box::get_cash_withdrawals)
+        .service(routes::cash_box::daily_close)
+
+        // Payout API
+        .service(routes::payouts::request_payout)
+        .service(routes::payouts::cancel_payout)
+        .service(routes::payouts::approve_payout)
+        .service(routes::payouts::reject_payout)
+        .service(routes::payouts::mark_payout_paid)
+        .service(routes::payouts::get_payouts)
+        .service(routes::payouts::get_own_payouts)
+
+        // Spending limit API
+        .service(routes::spending_limits::get_spending_limits)
+        .service(routes::spending_limits::set_spending_limits)
+
+        // Notification API
+        .service(routes::notifications::get_notifications)
+        .service(routes::notifications::get_unread_count)
+        .service(routes::notifications::mark_read)
+
+        // Favourites API
+        .service(routes::favourites::get_favourites)
+        .service(routes::favourites::add_favourite)
+        .service(routes::favourites::remove_favourite)
+        .service(routes::favourites::get_quick_carts)
+        .service(routes::favourites::save_quick_cart)
+        .service(routes::favourites::delete_quick_cart)
+        .service(routes::favourites::buy_quick_cart)
+        .service(routes::favourites::buy_again)
 
         // Stats API
         .service(routes::stats::best_selling_product)
@@ -1,6 +1,10 @@
+use actix_web::http::StatusCode;
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
-use crate::{Role, database::{model::{ProductRow, TransactionItemRow, TransactionRow, UserRow}}, routes::stats};
+use std::collections::HashMap;
+
+use crate::{Role, database::{model::{BundleComponentRow, BundleRow, CashCountRow, CashFlowRow, CashWithdrawalRow, DepositBonusRuleRow, DiscountRuleRow, PaymentMethodTotalRow, ProductPriceTierRow, ProductRow, SalesRow, SettlementPaymentRow, SpendingLimitRow, StampCardProgramRow, TransactionItemRow, TransactionRow, UserRow, VoucherRedemptionRow, VoucherRow}}, error::GenericError, routes::{payment::{PaymentMethod, swish}, stats}};
 
 #[derive(serde::Deserialize)]
 pub struct ProductParams {
@@ -8,8 +12,15 @@ pub struct ProductParams {
     pub name: Option<String>,
     pub price: Option<f32>,
     pub description: Option<String>,
+    pub category: Option<String>,
     pub stock: Option<i32>,
-    pub flags: Option<ProductFlags>
+    pub flags: Option<ProductFlags>,
+    pub allergens: Option<AllergenFlags>,
+    pub nutrition: Option<Nutrition>,
+    pub ingredients: Option<String>,
+    pub release_at: Option<i64>, // UNIX timestamp, 0 clears it
+    pub availability: Option<Availability>,
+    pub price_tiers: Option<Vec<TierPrice>>, // Replaces all tier prices
 }
 
 #[derive(Clone)]
@@ -18,45 +29,88 @@ pub struct Product {
     pub name: String,
     pub price: f32,
     pub description: String,
+    pub category: Option<String>,
     pub stock: Option<i32>,
     pub flags: ProductFlags,
+    pub allergens: AllergenFlags,
+    pub nutrition: Option<Nutrition>,
+    pub ingredients: Option<String>,
+    pub release_at: Option<i64>,
+    pub availability: Option<Availability>,
+    pub new_since: Option<i64>,
 }
 
 impl Product {
-    pub fn from_request(params: ProductParams) -> Result<Product, ()> {
-        Ok(Product { 
+    /// None if the name or price is missing
+    pub fn from_request(params: ProductParams) -> Option<Product> {
+        let mut product = Product { 
             id: 0,
-            name: params.name.ok_or(())?,
-            price: params.price.ok_or(())?, 
+            name: params.name?,
+            price: params.price?, 
             description: params.description.unwrap_or("".to_string()),
+            category: params.category.filter(|c| !c.is_empty()),
             stock: None,
-            flags: match params.flags {
-                Some(flags) => flags,
-                None => ProductFlags::default(),
-            },
-        })
+            flags: params.flags.unwrap_or_default(),
+            allergens: params.allergens.unwrap_or_default(),
+            nutrition: params.nutrition.filter(|n| !n.is_empty()),
+            ingredients: params.ingredients.filter(|i| !i.is_empty()),
+            release_at: params.release_at.filter(|r| *r > 0),
+            availability: params.availability.filter(|a| !a.is_unrestricted()),
+            new_since: None,
+        };
+        product.flags.popular = false;
+        if product.flags.new_product {
+            product.new_since = Some(product.released_at(OffsetDateTime::now_utc().unix_timestamp()));
+        }
+        Some(product)
     }
 
-    pub fn from_row(row: ProductRow) -> Result<Product, ()> {
-        Ok(Product { 
+    pub fn from_row(row: ProductRow) -> Product {
+        Product { 
             id: row.id,
             name: row.name,
             price: row.price,
             description: row.description,
+            category: row.category,
             stock: row.stock,
-            flags: row.flags.0
-        })
+            flags: row.flags.0,
+            allergens: row.allergens.0,
+            nutrition: row.nutrition.map(|n| n.0),
+            ingredients: row.ingredients,
+            release_at: row.release_at,
+            availability: row.availability.map(|a| a.0),
+            new_since: row.new_since,
+        }
     }
 
     pub fn update(&mut self, params: ProductParams) {
+        let was_new = self.flags.new_product;
+
         if let Some(name) = params.name { self.name = name };
         if let Some(price) = params.price { self.price = price };
         if let Some(description) = params.description { self.description = description };
+        if let Some(category) = params.category { self.category = Some(category).filter(|c| !c.is_empty()) };
         self.stock = params.stock;
 
         if let Some(flags) = params.flags {
-            self.flags = flags;
+            self.flags = ProductFlags { popular: self.flags.popular, ..flags };
         };
+        if let Some(allergens) = params.allergens { self.allergens = allergens };
+        // Empty values (and a zero release_at) clear the declaration
+        if let Some(nutrition) = params.nutrition { self.nutrition = Some(nutrition).filter(|n| !n.is_empty()) };
+        if let Some(ingredients) = params.ingredients { self.ingredients = Some(ingredients).filter(|i| !i.is_empty()) };
+        if let Some(release_at) = params.release_at { self.release_at = Some(release_at).filter(|r| *r > 0) };
+        if let Some(availability) = params.availability { self.availability = Some(availability).filter(|a| !a.is_unrestricted()) };
+
+        // Restart the new_product countdown whenever the flag is turned on
+        if self.flags.new_product && !was_new {
+            self.new_since = Some(self.released_at(OffsetDateTime::now_utc().unix_timestamp()));
+        }
+    }
+
+    /// When the product is, or will be, put up for sale
+    fn released_at(&self, now: i64) -> i64 {
+        self.release_at.map_or(now, |release_at| release_at.max(now))
     }
 
     pub fn into_row(self) -> ProductRow {
@@ -65,8 +119,15 @@ impl Product {
             name: self.name,
             price: self.price,
             description: self.description,
+            category: self.category,
             stock: self.stock,
-            flags: sqlx::types::Json(self.flags)
+            flags: sqlx::types::Json(self.flags),
+            allergens: sqlx::types::Json(self.allergens),
+            nutrition: self.nutrition.map(sqlx::types::Json),
+            ingredients: self.ingredients,
+            release_at: self.release_at,
+            availability: self.availability.map(sqlx::types::Json),
+            new_since: self.new_since,
         }
     }
 
@@ -77,12 +138,12 @@ impl Product {
 pub struct ProductFlags {
     pub modifiable: bool, // is only modifiable by admin
     pub new_product: bool,
-    pub popular: bool,
+    pub popular: bool, // computed hourly from recent sales, changes from requests are ignored
     pub marked_sold_out: bool,
 }
 
-impl ProductFlags {
-    pub fn default() -> ProductFlags {
+impl Default for ProductFlags {
+    fn default() -> ProductFlags {
         ProductFlags { 
             modifiable: true,
             new_product: false,
@@ -90,21 +151,579 @@ impl ProductFlags {
             marked_sold_out: false,
         }
     }
+}
+
+/// The 14 allergens that must be declared according to EU regulation 1169/2011
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Allergen {
+    Gluten,
+    Crustaceans,
+    Eggs,
+    Fish,
+    Peanuts,
+    Soybeans,
+    Milk, // Including lactose
+    Nuts,
+    Celery,
+    Mustard,
+    Sesame,
+    Sulphites,
+    Lupin,
+    Molluscs,
+}
+
+impl std::str::FromStr for Allergen {
+    type Err = GenericError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gluten" => Ok(Allergen::Gluten),
+            "crustaceans" => Ok(Allergen::Crustaceans),
+            "eggs" => Ok(Allergen::Eggs),
+            "fish" => Ok(Allergen::Fish),
+            "peanuts" => Ok(Allergen::Peanuts),
+            "soybeans" => Ok(Allergen::Soybeans),
+            "milk" | "lactose" => Ok(Allergen::Milk),
+            "nuts" => Ok(Allergen::Nuts),
+            "celery" => Ok(Allergen::Celery),
+            "mustard" => Ok(Allergen::Mustard),
+            "sesame" => Ok(Allergen::Sesame),
+            "sulphites" => Ok(Allergen::Sulphites),
+            "lupin" => Ok(Allergen::Lupin),
+            "molluscs" => Ok(Allergen::Molluscs),
+            unknown => Err(GenericError::new("Could not parse allergen")
+                .with_status(StatusCode::BAD_REQUEST)
+                .add_info(format!("Found {unknown}")))
+        }
+    }
+}
+
+/// Which of the EU allergens a product contains
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AllergenFlags {
+    pub gluten: bool,
+    pub crustaceans: bool,
+    pub eggs: bool,
+    pub fish: bool,
+    pub peanuts: bool,
+    pub soybeans: bool,
+    pub milk: bool,
+    pub nuts: bool,
+    pub celery: bool,
+    pub mustard: bool,
+    pub sesame: bool,
+    pub sulphites: bool,
+    pub lupin: bool,
+    pub molluscs: bool,
+}
+
+impl AllergenFlags {
+    pub fn contains(&self, allergen: Allergen) -> bool {
+        match allergen {
+            Allergen::Gluten => self.gluten,
+            Allergen::Crustaceans => self.crustaceans,
+            Allergen::Eggs => self.eggs,
+            Allergen::Fish => self.fish,
+            Allergen::Peanuts => self.peanuts,
+            Allergen::Soybeans => self.soybeans,
+            Allergen::Milk => self.milk,
+            Allergen::Nuts => self.nuts,
+            Allergen::Celery => self.celery,
+            Allergen::Mustard => self.mustard,
+            Allergen::Sesame => self.sesame,
+            Allergen::Sulphites => self.sulphites,
+            Allergen::Lupin => self.lupin,
+            Allergen::Molluscs => self.molluscs,
+        }
+    }
+}
+
+/// Nutrition declaration, all values per 100 g
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Nutrition {
+    pub energy_kcal: Option<f32>,
+    pub fat: Option<f32>,
+    pub saturated_fat: Option<f32>,
+    pub carbohydrates: Option<f32>,
+    pub sugars: Option<f32>,
+    pub protein: Option<f32>,
+    pub salt: Option<f32>,
+}
+
+impl Nutrition {
+    pub fn is_empty(&self) -> bool {
+        [self.energy_kcal, self.fat, self.saturated_fat, self.carbohydrates, self.sugars, self.protein, self.salt]
+            .iter().all(Option::is_none)
+    }
+}
+
+/// Recurring window during which a product can be bought, in the store's local time
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Availability {
+    /// ISO weekday numbers (1 = Monday). Empty means every day
+    pub weekdays: Vec<u8>,
+    /// "HH:MM", inclusive
+    pub from: Option<String>,
+    /// "HH:MM", exclusive. May be earlier than `from` to span midnight
+    pub until: Option<String>,
+}
+
+impl Availability {
+    pub fn is_unrestricted(&self) -> bool {
+        self.weekdays.is_empty() && self.from.is_none() && self.until.is_none()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.weekdays.iter().all(|d| (1..=7).contains(d))
+            && [&self.from, &self.until].iter().all(|t| t.as_deref().is_none_or(|t| minutes_of_day(t).is_some()))
+    }
+
+    /// `local_time` must already be converted to the store's time zone
+    pub fn is_open(&self, local_time: OffsetDateTime) -> bool {
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&local_time.weekday().number_from_monday()) {
+            return false;
+        }
+        let now = local_time.hour() as u16 * 60 + local_time.minute() as u16;
+        let from = self.from.as_deref().and_then(minutes_of_day);
+        let until = self.until.as_deref().and_then(minutes_of_day);
+        match (from, until) {
+            (Some(from), Some(until)) if from > until => now >= from || now < until,
+            (from, until) => from.is_none_or(|from| now >= from) && until.is_none_or(|until| now < until),
+        }
+    }
+}
+
+/// Parses "HH:MM" into minutes since midnight
+fn minutes_of_day(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+/// Price tiers, keyed on membership or role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "price_tier", rename_all = "snake_case")]
+pub enum PriceTier {
+    Member,
+    NonMember,
+    Bot,
+    Maintainer,
+    Admin,
+}
+
+impl PriceTier {
+    /// Tiers that apply to `user`, most specific first
+    pub fn candidates(user: &UserRow) -> Vec<PriceTier> {
+        let membership = match user.is_member {
+            true => PriceTier::Member,
+            false => PriceTier::NonMember,
+        };
+        match user.role {
+            Role::Admin => vec![PriceTier::Admin, membership],
+            Role::Maintainer => vec![PriceTier::Maintainer, membership],
+            Role::Bot => vec![PriceTier::Bot, membership],
+            Role::User => vec![membership],
+        }
+    }
+
+    /// Picks the most specific of the user's tiers with a price in `tier_prices`
+    pub fn resolve(user: &UserRow, tier_prices: &[ProductPriceTierRow]) -> Option<(PriceTier, f32)> {
+        PriceTier::candidates(user).into_iter().find_map(|tier| {
+            tier_prices.iter().find(|t| t.tier == tier).map(|t| (tier, t.price))
+        })
+    }
+}
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct TierPrice {
+    pub tier: PriceTier,
+    pub price: f32,
+}
+
+/// Which products a discount rule or stamp card applies to
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProductTarget {
+    All,
+    Product { id: u32 },
+    Category { name: String },
+}
+
+impl ProductTarget {
+    pub fn matches(&self, product: &ProductRow) -> bool {
+        match self {
+            ProductTarget::All => true,
+            ProductTarget::Product { id } => *id == product.id,
+            ProductTarget::Category { name } => product.category.as_ref().is_some_and(|c| c.to_lowercase() == name.to_lowercase()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Discount {
+    Percentage(f32), // 0-100
+    Fixed(f32), // Kronor off each unit
+}
+
+impl Discount {
+    /// Amount taken off `price`, never more than the price itself
+    pub fn reduction(&self, price: f32) -> f32 {
+        let reduction = match self {
+            Discount::Percentage(percentage) => price * percentage / 100.0,
+            Discount::Fixed(amount) => *amount,
+        };
+        reduction.clamp(0.0, price.max(0.0))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Discount::Percentage(percentage) => (0.0..=100.0).contains(percentage),
+            Discount::Fixed(amount) => *amount >= 0.0,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DiscountRuleParams {
+    pub id: Option<u32>, // Only used when updating
+    pub name: String,
+    pub target: ProductTarget,
+    pub discount: Discount,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    pub time_window: Option<Availability>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+fn default_true() -> bool { true }
+
+impl DiscountRuleParams {
+    pub fn into_row(self, id: u32) -> DiscountRuleRow {
+        DiscountRuleRow {
+            id,
+            name: self.name,
+            target: sqlx::types::Json(self.target),
+            discount: sqlx::types::Json(self.discount),
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+            time_window: self.time_window.filter(|w| !w.is_unrestricted()).map(sqlx::types::Json),
+            active: self.active,
+        }
+    }
+}
 
-    pub fn from_str(string: &str) -> Result<ProductFlags, ()> {
-        serde_json::from_str::<ProductFlags>(string).map_err(|_| ())
+#[derive(serde::Deserialize)]
+pub struct BundleParams {
+    pub id: Option<u32>, // Only used when updating
+    pub name: String,
+    pub price: f32,
+    pub components: Vec<BundleComponentRow>,
+    #[serde(default)]
+    pub auto_apply: bool,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+impl BundleParams {
+    pub fn into_rows(self, id: u32) -> (BundleRow, Vec<BundleComponentRow>) {
+        let row = BundleRow { id, name: self.name, price: self.price, auto_apply: self.auto_apply, active: self.active };
+        let components = self.components.into_iter().map(|c| BundleComponentRow { bundle: id, ..c }).collect();
+        (row, components)
     }
+}
 
-    pub fn to_string(&self) -> String {
-        serde_json::to_string(self).unwrap()
+#[derive(Clone, serde::Serialize)]
+pub struct Bundle {
+    pub id: u32,
+    pub name: String,
+    pub price: f32,
+    pub auto_apply: bool,
+    pub active: bool,
+    pub components: Vec<BundleComponentRow>,
+}
+
+impl Bundle {
+    pub fn from_rows(row: BundleRow, components: Vec<BundleComponentRow>) -> Self {
+        Bundle {
+            id: row.id,
+            name: row.name,
+            price: row.price,
+            auto_apply: row.auto_apply,
+            active: row.active,
+            components,
+        }
+    }
+
+    /// Price of the components bought separately, `None` if a component is missing from `products`
+    pub fn full_price(&self, products: &HashMap<u32, ProductRow>) -> Option<f32> {
+        self.components.iter()
+            .map(|c| products.get(&c.product).map(|p| p.price * c.quantity as f32))
+            .sum()
+    }
+
+    /// How many times the bundle fits in `quantities` (product id to quantity)
+    pub fn fits(&self, quantities: &HashMap<u32, u32>) -> u32 {
+        self.components.iter()
+            .map(|c| quantities.get(&c.product).copied().unwrap_or(0) / c.quantity)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The components of `count` bundles, with the bundle saving spread over them
+    /// in proportion to their price
+    pub fn pending_items(&self, count: u32, products: &HashMap<u32, ProductRow>) -> Vec<PendingItem> {
+        let full_price = self.full_price(products).unwrap_or(0.0);
+        let saving_ratio = match full_price > 0.0 {
+            true => ((full_price - self.price) / full_price).clamp(0.0, 1.0),
+            false => 0.0,
+        };
+        self.components.iter()
+            .filter_map(|c| products.get(&c.product).map(|product| PendingItem {
+                product: product.clone(),
+                quantity: c.quantity * count,
+                discount: product.price * saving_ratio,
+                discount_rule: None,
+                bundle: Some(self.id),
+                price_tier: None,
+                stamp_program: None,
+            }))
+            .collect()
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct StampCardProgramParams {
+    pub id: Option<u32>, // Only used when updating
+    pub name: String,
+    pub target: ProductTarget,
+    pub stamps_required: u32,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+impl StampCardProgramParams {
+    pub fn into_row(self, id: u32) -> StampCardProgramRow {
+        StampCardProgramRow {
+            id,
+            name: self.name,
+            target: sqlx::types::Json(self.target),
+            stamps_required: self.stamps_required,
+            active: self.active,
+        }
+    }
+}
+
+/// A user's progress on a stamp card program
+#[derive(serde::Serialize)]
+pub struct StampCard {
+    pub program: StampCardProgramRow,
+    pub stamps: u32, // Towards the next reward
+    pub rewards_available: u32,
+}
+
+impl StampCard {
+    pub fn new(program: StampCardProgramRow, total_stamps: i64) -> Self {
+        let total_stamps = total_stamps.max(0) as u32;
+        StampCard {
+            stamps: total_stamps % program.stamps_required,
+            rewards_available: total_stamps / program.stamps_required,
+            program,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DepositBonus {
+    Percentage(f32), // Of the deposited amount
+    Fixed(f32), // Kronor added to the deposit
+}
+
+impl DepositBonus {
+    pub fn amount(&self, deposit: f32) -> f32 {
+        let amount = match self {
+            DepositBonus::Percentage(percentage) => deposit * percentage / 100.0,
+            DepositBonus::Fixed(amount) => *amount,
+        };
+        amount.max(0.0)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            DepositBonus::Percentage(percentage) => (0.0..=100.0).contains(percentage),
+            DepositBonus::Fixed(amount) => *amount >= 0.0,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DepositBonusRuleParams {
+    pub id: Option<u32>, // Only used when updating
+    pub name: String,
+    pub bonus: DepositBonus,
+    #[serde(default)]
+    pub min_amount: f32,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+impl DepositBonusRuleParams {
+    pub fn into_row(self, id: u32) -> DepositBonusRuleRow {
+        DepositBonusRuleRow {
+            id,
+            name: self.name,
+            bonus: sqlx::types::Json(self.bonus),
+            min_amount: self.min_amount,
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+            active: self.active,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct VoucherParams {
+    pub code: Option<String>, // Generated if not given, only allowed when creating a single code
+    pub value: f32,
+    #[serde(default = "default_one")]
+    pub max_redemptions: u32,
+    pub expires_at: Option<i64>,
+    pub note: Option<String>,
+    #[serde(default = "default_one")]
+    pub count: u32, // Number of codes to generate
+}
+
+fn default_one() -> u32 { 1 }
+
+/// Voucher with everyone who has redeemed it, for admins
+#[derive(serde::Serialize)]
+pub struct Voucher {
+    #[serde(flatten)]
+    pub voucher: VoucherRow,
+    pub redemptions: Vec<VoucherRedemptionRow>,
+}
+
+impl Voucher {
+    pub fn from_rows(vouchers: Vec<VoucherRow>, mut redemptions: Vec<VoucherRedemptionRow>) -> Vec<Voucher> {
+        vouchers.into_iter().map(|voucher| {
+            let (own, rest) = redemptions.drain(..).partition(|r| r.voucher == voucher.id);
+            redemptions = rest;
+            Voucher { voucher, redemptions: own }
+        }).collect()
+    }
+}
+
+/// What a ledger entry represents, purchases have a negative amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "transaction_kind", rename_all = "snake_case")]
+pub enum TransactionKind {
+    Purchase,
+    Deposit,
+    Adjustment, // Balance changed by an admin
+    Bonus, // Promotional top-up on a deposit
+    Voucher, // Redeemed voucher code
+    Refund, // Deposit paid back through Swish or in cash, positive if the refund failed
+    Payout, // Balance paid out to the user, positive if the payout was rejected or cancelled
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "notification_kind", rename_all = "snake_case")]
+pub enum NotificationKind {
+    Deposit, // A deposit was credited
+    Adjustment, // An admin changed the balance
+    Restock, // A favourite product is back in stock
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "payout_status", rename_all = "snake_case")]
+pub enum PayoutStatus {
+    Requested,
+    Approved,
+    Paid,
+    Rejected,
+    Cancelled,
+}
+
+impl PayoutStatus {
+    /// The amount is still held from the user's balance and may be paid out
+    pub fn is_open(&self) -> bool {
+        matches!(self, PayoutStatus::Requested | PayoutStatus::Approved)
     }
 }
 
 pub struct PendingTransaction {
     pub user: Option<u32>, // None if user has private_transactions
     pub amount: f32,
-    pub products: Vec<(ProductRow, u32)>,
-    pub admin_issued: bool
+    pub products: Vec<PendingItem>,
+    pub admin_issued: bool,
+    pub price_tier: Option<PriceTier>,
+    pub kind: TransactionKind,
+    pub payment_reference: Option<String>,
+    pub note: Option<String>,
+    pub payment_method: Option<PaymentMethod>, // Set on deposits and refunds
+}
+
+impl PendingTransaction {
+    /// Ledger entry without any products
+    pub fn balance_change(user: u32, amount: f32, kind: TransactionKind) -> Self {
+        PendingTransaction {
+            user: Some(user),
+            amount,
+            products: Vec::new(),
+            admin_issued: kind == TransactionKind::Adjustment,
+            price_tier: None,
+            kind,
+            payment_reference: None,
+            note: None,
+            payment_method: None,
+        }
+    }
+}
+
+pub struct PendingItem {
+    pub product: ProductRow,
+    pub quantity: u32,
+    pub discount: f32, // Per unit
+    pub discount_rule: Option<u32>,
+    pub bundle: Option<u32>,
+    pub price_tier: Option<PriceTier>, // None if the base price is charged
+    pub stamp_program: Option<u32>, // Set if this is a free stamp card reward
+}
+
+impl PendingItem {
+    pub fn new(product: ProductRow, quantity: u32) -> Self {
+        PendingItem { product, quantity, discount: 0.0, discount_rule: None, bundle: None, price_tier: None, stamp_program: None }
+    }
+
+    /// Applies the rule giving the largest reduction, discounts do not stack.
+    /// `local_time` must be in the store's time zone
+    pub fn apply_best_discount(&mut self, rules: &[DiscountRuleRow], local_time: OffsetDateTime) {
+        for rule in rules.iter().filter(|r| r.applies_to(&self.product, local_time)) {
+            let reduction = rule.discount.reduction(self.product.price);
+            if reduction > self.discount {
+                self.discount = reduction;
+                self.discount_rule = Some(rule.id);
+            }
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        (self.product.price - self.discount) * self.quantity as f32
+    }
 }
 
 #[derive(serde::Serialize)]
@@ -112,7 +731,12 @@ pub struct TransactionItem {
     pub product_id: u32,
     pub name: String,
     pub price: f32,
-    pub quantity: u32
+    pub quantity: u32,
+    pub discount: f32,
+    pub discount_rule: Option<u32>,
+    pub bundle: Option<u32>,
+    pub price_tier: Option<PriceTier>,
+    pub stamp_program: Option<u32>,
 }
 
 impl From<TransactionItemRow> for TransactionItem {
@@ -121,7 +745,12 @@ impl From<TransactionItemRow> for TransactionItem {
             product_id: row.product,
             name: row.name,
             price: row.price,
-            quantity: row.quantity
+            quantity: row.quantity,
+            discount: row.discount,
+            discount_rule: row.discount_rule,
+            bundle: row.bundle,
+            price_tier: row.price_tier,
+            stamp_program: row.stamp_program,
         }
     }
 }
@@ -133,6 +762,10 @@ pub struct TransactionDetail {
     pub user: Option<UserResponse>, // None if user has private_transactions
     pub datetime: i64,
     pub admin_issued: bool,
+    pub price_tier: Option<PriceTier>,
+    pub kind: TransactionKind,
+    pub note: Option<String>,
+    pub payment_method: Option<PaymentMethod>,
     items: Vec<TransactionItem>
 }
 
@@ -142,6 +775,9 @@ pub struct TransactionSummary {
     pub amount: f32,
     pub user_email: String,
     pub admin_issued: bool,
+    pub kind: TransactionKind,
+    pub note: Option<String>,
+    pub payment_method: Option<PaymentMethod>,
     pub datetime: i64,
 }
 
@@ -163,6 +799,10 @@ impl TransactionDetail {
             user: user_response,
             datetime: transaction.datetime,
             admin_issued: transaction.admin_issued,
+            price_tier: transaction.price_tier,
+            kind: transaction.kind,
+            note: transaction.note,
+            payment_method: transaction.payment_method,
             items: Vec::new()
         }
     }
@@ -175,11 +815,101 @@ pub struct TransactionQuery {
     pub time_range: Option<stats::TimeRange>,
     pub search_term: Option<String>,
     pub admin_issued: Option<bool>,
+    #[serde(default)]
+    pub kinds: Vec<TransactionKind>, // Any of, all kinds if empty
     pub cursor: Option<TimeIdCursor>, // pagination
     pub limit: u32,
     pub descending: bool,
 }
 
+#[derive(Deserialize)]
+pub struct PaymentRequestQuery {
+    #[serde(default)]
+    pub user_ids: Vec<u32>, // Any of, all users if empty
+    #[serde(default)]
+    pub statuses: Vec<swish::Status>, // Any of, all statuses if empty
+    pub created_after: Option<i64>, // UNIX timestamp, inclusive
+    pub created_before: Option<i64>, // UNIX timestamp, exclusive
+    pub payment_reference: Option<String>,
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// Sent with a settlement report, columns are found by their usual Swedish or English names if not given
+#[derive(Deserialize, Default)]
+pub struct SettlementOptions {
+    pub reference_column: Option<String>,
+    pub amount_column: Option<String>,
+    pub date_column: Option<String>,
+    pub start: Option<i64>, // UNIX timestamp, defaults to the first booking date in the report
+    pub end: Option<i64>, // UNIX timestamp, defaults to the day after the last booking date
+}
+
+#[derive(Serialize)]
+pub struct SettlementEntry {
+    pub payment_reference: String,
+    pub line: Option<u64>, // Line in the report, None if missing in the bank
+    pub bank_amount: Option<f32>,
+    pub payment: Option<SettlementPaymentRow>, // None if missing in the system
+}
+
+#[derive(Serialize)]
+pub struct SettlementIgnoredLine {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Serialize)]
+pub struct SettlementReport {
+    pub start: i64,
+    pub end: i64,
+    pub bank_total: f32,
+    pub system_total: f32, // Paid within start..end
+    pub matched: Vec<SettlementEntry>,
+    /// Found in both, but the bank, the payment request and the credited deposits disagree on the amount
+    /// or the payment is not marked as paid
+    pub mismatched: Vec<SettlementEntry>,
+    pub missing_in_system: Vec<SettlementEntry>,
+    pub missing_in_bank: Vec<SettlementEntry>,
+    pub ignored: Vec<SettlementIgnoredLine>,
+}
+
+#[derive(Serialize)]
+pub struct CashCount {
+    #[serde(flatten)]
+    pub count: CashCountRow,
+    pub difference: f32, // Positive if there was more cash in the box than expected
+}
+
+impl From<CashCountRow> for CashCount {
+    fn from(count: CashCountRow) -> Self {
+        CashCount { difference: count.counted - count.expected, count }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CashBoxStatus {
+    pub expected: f32,
+    pub last_count: Option<CashCount>, // Baseline for `expected`, None if the box has never been counted
+    pub since_last_count: CashFlowRow,
+}
+
+#[derive(Serialize)]
+pub struct DailyCloseReport {
+    pub date: String,
+    pub start: i64,
+    pub end: i64,
+    pub sales: SalesRow,
+    pub deposits: Vec<PaymentMethodTotalRow>,
+    pub refunds: Vec<PaymentMethodTotalRow>,
+    pub cash: CashFlowRow,
+    pub withdrawals: Vec<CashWithdrawalRow>,
+    pub counts: Vec<CashCount>,
+    pub expected_at_close: f32,
+    pub difference: Option<f32>, // Of the day's last count
+}
+
 #[derive(Deserialize)]
 pub struct TimeIdCursor {
     pub datetime: i64, // UNIX timestamp
@@ -194,7 +924,14 @@ pub struct UserResponse {
     pub balance: f32,
     pub role: Role,
     pub on_leaderboard: bool,
-    pub private_transactions: bool
+    pub private_transactions: bool,
+    pub is_member: bool,
+    pub low_balance_alerts: bool,
+    pub low_balance_threshold: f32,
+    pub email_receipts: bool,
+    pub monthly_statements: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spending_budget: Option<SpendingBudget>, // Only on the user's own profile and if they have a spending limit
 }
 
 impl From<UserRow> for UserResponse {
@@ -207,6 +944,67 @@ impl From<UserRow> for UserResponse {
             role: row.role,
             on_leaderboard: row.on_leaderboard,
             private_transactions: row.private_transactions,
+            is_member: row.is_member,
+            low_balance_alerts: row.low_balance_alerts,
+            low_balance_threshold: row.low_balance_threshold,
+            email_receipts: row.email_receipts,
+            monthly_statements: row.monthly_statements,
+            spending_budget: None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct SpendingBudget {
+    pub spent_today: f32,
+    pub spent_this_week: f32,
+    pub daily_remaining: Option<f32>,
+    pub weekly_remaining: Option<f32>,
+    pub remaining: Option<f32>, // The smaller of the two, None if neither is capped
+}
+
+impl SpendingBudget {
+    pub fn new(limit: &SpendingLimitRow, spent_today: f32, spent_this_week: f32) -> Self {
+        let daily_remaining = limit.daily.map(|daily| (daily - spent_today).max(0.0));
+        let weekly_remaining = limit.weekly.map(|weekly| (weekly - spent_this_week).max(0.0));
+        let remaining = match (daily_remaining, weekly_remaining) {
+            (Some(daily), Some(weekly)) => Some(daily.min(weekly)),
+            (daily, weekly) => daily.or(weekly),
+        };
+        SpendingBudget { spent_today, spent_this_week, daily_remaining, weekly_remaining, remaining }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PendingSpendingLimit {
+    pub daily: Option<f32>,
+    pub weekly: Option<f32>,
+    pub cooldown_days: u32,
+    pub applies_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct SpendingLimit {
+    pub daily: Option<f32>,
+    pub weekly: Option<f32>,
+    pub cooldown_days: u32,
+    pub pending: Option<PendingSpendingLimit>, // Loosened limits waiting out the cooldown
+    pub budget: SpendingBudget,
+}
+
+impl SpendingLimit {
+    pub fn new(limit: SpendingLimitRow, budget: SpendingBudget) -> Self {
+        SpendingLimit {
+            daily: limit.daily,
+            weekly: limit.weekly,
+            cooldown_days: limit.cooldown_days,
+            pending: limit.pending_at.map(|applies_at| PendingSpendingLimit {
+                daily: limit.pending_daily,
+                weekly: limit.pending_weekly,
+                cooldown_days: limit.pending_cooldown_days.unwrap_or(limit.cooldown_days),
+                applies_at,
+            }),
+            budget,
         }
     }
 }
@@ -0,0 +1,55 @@
+use actix_web::{get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::{crud, model::DepositBonusRuleRow}, error::ApiResult, model::DepositBonusRuleParams, return_err};
+
+#[derive(serde::Deserialize)]
+struct DepositBonusRuleIdJson { id: u32 }
+
+fn assert_valid_rule(params: &DepositBonusRuleParams) -> ApiResult<()> {
+    if !params.bonus.is_valid() {
+        return_err!(actix_web::error::ErrorBadRequest("Invalid bonus"));
+    }
+    if params.min_amount < 0.0 {
+        return_err!(actix_web::error::ErrorBadRequest("Minimum deposit cannot be negative"));
+    }
+    if let (Some(start), Some(end)) = (params.starts_at, params.ends_at)
+        && start >= end {
+        return_err!(actix_web::error::ErrorBadRequest("Bonus rule ends before it starts"));
+    }
+    Ok(())
+}
+
+#[get("/api/get_deposit_bonus_rules")]
+pub async fn get_deposit_bonus_rules(state: Data<AppState>) -> ApiResult<Json<Vec<DepositBonusRuleRow>>> {
+    let rules = crud::get_deposit_bonus_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/create_deposit_bonus_rule")]
+pub async fn create_deposit_bonus_rule(state: Data<AppState>, params: web::Json<DepositBonusRuleParams>) -> ApiResult<Json<Vec<DepositBonusRuleRow>>> {
+    assert_valid_rule(&params)?;
+    crud::create_deposit_bonus_rule(&state.db, params.into_inner().into_row(0)).await?;
+
+    let rules = crud::get_deposit_bonus_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/update_deposit_bonus_rule")]
+pub async fn update_deposit_bonus_rule(state: Data<AppState>, params: web::Json<DepositBonusRuleParams>) -> ApiResult<Json<Vec<DepositBonusRuleRow>>> {
+    let Some(id) = params.id else {
+        return_err!(actix_web::error::ErrorBadRequest("Missing required argument \"id\""));
+    };
+    assert_valid_rule(&params)?;
+    crud::update_deposit_bonus_rule(&state.db, params.into_inner().into_row(id)).await?;
+
+    let rules = crud::get_deposit_bonus_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/delete_deposit_bonus_rule")]
+pub async fn delete_deposit_bonus_rule(state: Data<AppState>, params: web::Json<DepositBonusRuleIdJson>) -> ApiResult<Json<Vec<DepositBonusRuleRow>>> {
+    crud::delete_deposit_bonus_rule(&state.db, params.id).await?;
+
+    let rules = crud::get_deposit_bonus_rules(&state.db).await?;
+    Ok(Json(rules))
+}
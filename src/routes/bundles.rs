@@ -0,0 +1,62 @@
+use actix_web::{get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::crud, error::ApiResult, model::{Bundle, BundleParams}, return_err};
+
+#[derive(serde::Deserialize)]
+struct BundleIdJson { id: u32 }
+
+async fn assert_valid_bundle(state: &Data<AppState>, params: &BundleParams) -> ApiResult<()> {
+    if params.price < 0.0 {
+        return_err!(actix_web::error::ErrorBadRequest("Bundle price cannot be negative"));
+    }
+    if params.components.is_empty() {
+        return_err!(actix_web::error::ErrorBadRequest("Bundle has no components"));
+    }
+    for component in &params.components {
+        if component.quantity == 0 {
+            return_err!(actix_web::error::ErrorBadRequest("Bundle component quantity must be positive"));
+        }
+        if params.components.iter().filter(|c| c.product == component.product).count() > 1 {
+            return_err!(actix_web::error::ErrorBadRequest("Bundle component listed twice"));
+        }
+        crud::get_product(&state.db, component.product).await?;
+    }
+    Ok(())
+}
+
+#[get("/api/get_bundles")]
+pub async fn get_bundles(state: Data<AppState>) -> ApiResult<Json<Vec<Bundle>>> {
+    let bundles = crud::get_bundles(&state.db).await?;
+    Ok(Json(bundles))
+}
+
+#[post("/api/create_bundle")]
+pub async fn create_bundle(state: Data<AppState>, params: web::Json<BundleParams>) -> ApiResult<Json<Vec<Bundle>>> {
+    assert_valid_bundle(&state, &params).await?;
+    let (bundle, components) = params.into_inner().into_rows(0);
+    crud::create_bundle(&state.db, bundle, components).await?;
+
+    let bundles = crud::get_bundles(&state.db).await?;
+    Ok(Json(bundles))
+}
+
+#[post("/api/update_bundle")]
+pub async fn update_bundle(state: Data<AppState>, params: web::Json<BundleParams>) -> ApiResult<Json<Vec<Bundle>>> {
+    let Some(id) = params.id else {
+        return_err!(actix_web::error::ErrorBadRequest("Missing required argument \"id\""));
+    };
+    assert_valid_bundle(&state, &params).await?;
+    let (bundle, components) = params.into_inner().into_rows(id);
+    crud::update_bundle(&state.db, bundle, components).await?;
+
+    let bundles = crud::get_bundles(&state.db).await?;
+    Ok(Json(bundles))
+}
+
+#[post("/api/delete_bundle")]
+pub async fn delete_bundle(state: Data<AppState>, params: web::Json<BundleIdJson>) -> ApiResult<Json<Vec<Bundle>>> {
+    crud::delete_bundle(&state.db, params.id).await?;
+
+    let bundles = crud::get_bundles(&state.db).await?;
+    Ok(Json(bundles))
+}
@@ -0,0 +1,117 @@
+use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+use time::{Date, Duration, UtcDateTime, macros::format_description};
+
+use crate::{AppState, database::{crud, model::CashWithdrawalRow}, error::ApiResult, model::{CashBoxStatus, CashCount, DailyCloseReport, TransactionKind}, return_err, routes::user_from_cookie, utils};
+
+/// Cash expected in the box at `at`: the last count before it plus everything that moved since
+async fn expected_cash(state: &Data<AppState>, at: i64) -> ApiResult<CashBoxStatus> {
+    let last_count = crud::get_last_cash_count(&state.db, at).await?;
+    let since_last_count = crud::get_cash_flow(&state.db, 0, at, last_count.as_ref()).await?;
+    let baseline = last_count.as_ref().map_or(0.0, |count| count.counted);
+    Ok(CashBoxStatus {
+        expected: baseline + since_last_count.net(),
+        last_count: last_count.map(CashCount::from),
+        since_last_count,
+    })
+}
+
+#[get("/api/cash_box")]
+pub async fn get_cash_box(state: Data<AppState>) -> ApiResult<Json<CashBoxStatus>> {
+    Ok(Json(expected_cash(&state, UtcDateTime::now().unix_timestamp() + 1).await?))
+}
+
+#[derive(serde::Deserialize)]
+struct WithdrawalJson {
+    amount: f32,
+    note: String, // What the cash is for, e.g. restock receipt
+}
+
+#[post("/api/cash_box/withdraw")]
+pub async fn withdraw_cash(state: Data<AppState>, req: HttpRequest, body: Json<WithdrawalJson>) -> ApiResult<Json<CashWithdrawalRow>> {
+    let maintainer = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if body.amount <= 0.0 || !body.amount.is_finite() {
+        return_err!(actix_web::error::ErrorBadRequest("amount must be positive"));
+    }
+    if body.note.trim().is_empty() {
+        return_err!(actix_web::error::ErrorBadRequest("A note on what the cash is for is required"));
+    }
+    let withdrawal = crud::create_cash_withdrawal(&state.db, body.amount, body.note, maintainer.id).await?;
+    log::info!("User {} withdrew {} kr from the cash box", maintainer.id, withdrawal.amount);
+    Ok(Json(withdrawal))
+}
+
+#[derive(serde::Deserialize)]
+struct CountJson {
+    counted: f32,
+    note: Option<String>,
+}
+
+/// Records the cash counted in the box, which becomes the baseline for what is expected from now on
+#[post("/api/cash_box/count")]
+pub async fn count_cash(state: Data<AppState>, req: HttpRequest, body: Json<CountJson>) -> ApiResult<Json<CashCount>> {
+    let maintainer = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if body.counted < 0.0 || !body.counted.is_finite() {
+        return_err!(actix_web::error::ErrorBadRequest("counted must not be negative"));
+    }
+    let expected = expected_cash(&state, UtcDateTime::now().unix_timestamp() + 1).await?.expected;
+    let count = CashCount::from(crud::create_cash_count(&state.db, body.counted, expected, body.note, maintainer.id).await?);
+    if count.difference.abs() >= 0.005 {
+        log::warn!("Cash count by user {} is off by {} kr", maintainer.id, count.difference);
+    }
+    Ok(Json(count))
+}
+
+#[derive(serde::Deserialize)]
+struct PeriodQuery {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+#[get("/api/cash_box/counts")]
+pub async fn get_cash_counts(state: Data<AppState>, query: web::Query<PeriodQuery>) -> ApiResult<Json<Vec<CashCount>>> {
+    let counts = crud::get_cash_counts(&state.db, query.start.unwrap_or(0), query.end.unwrap_or(i64::MAX)).await?;
+    Ok(Json(counts.into_iter().map(CashCount::from).collect()))
+}
+
+#[get("/api/cash_box/withdrawals")]
+pub async fn get_cash_withdrawals(state: Data<AppState>, query: web::Query<PeriodQuery>) -> ApiResult<Json<Vec<CashWithdrawalRow>>> {
+    let withdrawals = crud::get_cash_withdrawals(&state.db, query.start.unwrap_or(0), query.end.unwrap_or(i64::MAX)).await?;
+    Ok(Json(withdrawals))
+}
+
+#[derive(serde::Deserialize)]
+struct DailyCloseQuery {
+    date: Option<String>, // YYYY-MM-DD in the store's time zone, today if not given
+}
+
+/// Sales, deposits and refunds per payment method and the cash box's movements for one day
+#[get("/api/cash_box/daily_close")]
+pub async fn daily_close(state: Data<AppState>, query: web::Query<DailyCloseQuery>) -> ApiResult<Json<DailyCloseReport>> {
+    let date = match &query.date {
+        Some(date) => Date::parse(date, format_description!("[year]-[month]-[day]"))
+            .map_err(|_| actix_web::error::ErrorBadRequest("date must be formatted as YYYY-MM-DD"))?,
+        None => utils::now_local(state.env.timezone).date(),
+    };
+    let start = utils::local_midnight(date, state.env.timezone);
+    let end = utils::local_midnight(date + Duration::days(1), state.env.timezone);
+
+    let counts: Vec<CashCount> = crud::get_cash_counts(&state.db, start, end).await?
+        .into_iter().map(CashCount::from).collect();
+    let expected_at_close = expected_cash(&state, end).await?.expected;
+
+    Ok(Json(DailyCloseReport {
+        date: date.to_string(),
+        start,
+        end,
+        sales: crud::get_sales(&state.db, start, end).await?,
+        deposits: crud::get_payment_method_totals(&state.db, TransactionKind::Deposit, start, end).await?,
+        refunds: crud::get_payment_method_totals(&state.db, TransactionKind::Refund, start, end).await?,
+        cash: crud::get_cash_flow(&state.db, start, end, None).await?,
+        withdrawals: crud::get_cash_withdrawals(&state.db, start, end).await?,
+        difference: counts.last().map(|count| count.difference),
+        counts,
+        expected_at_close,
+    }))
+}
@@ -0,0 +1,58 @@
+use actix_web::{get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::{crud, model::DiscountRuleRow}, error::ApiResult, model::{DiscountRuleParams, ProductTarget}, return_err};
+
+#[derive(serde::Deserialize)]
+struct DiscountRuleIdJson { id: u32 }
+
+async fn assert_valid_rule(state: &Data<AppState>, params: &DiscountRuleParams) -> ApiResult<()> {
+    if !params.discount.is_valid() {
+        return_err!(actix_web::error::ErrorBadRequest("Invalid discount"));
+    }
+    if params.time_window.as_ref().is_some_and(|w| !w.is_valid()) {
+        return_err!(actix_web::error::ErrorBadRequest("Invalid time window"));
+    }
+    if let (Some(start), Some(end)) = (params.starts_at, params.ends_at)
+        && start >= end {
+        return_err!(actix_web::error::ErrorBadRequest("Discount rule ends before it starts"));
+    }
+    if let ProductTarget::Product { id } = params.target {
+        crud::get_product(&state.db, id).await?;
+    }
+    Ok(())
+}
+
+#[get("/api/get_discount_rules")]
+pub async fn get_discount_rules(state: Data<AppState>) -> ApiResult<Json<Vec<DiscountRuleRow>>> {
+    let rules = crud::get_discount_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/create_discount_rule")]
+pub async fn create_discount_rule(state: Data<AppState>, params: web::Json<DiscountRuleParams>) -> ApiResult<Json<Vec<DiscountRuleRow>>> {
+    assert_valid_rule(&state, &params).await?;
+    crud::create_discount_rule(&state.db, params.into_inner().into_row(0)).await?;
+
+    let rules = crud::get_discount_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/update_discount_rule")]
+pub async fn update_discount_rule(state: Data<AppState>, params: web::Json<DiscountRuleParams>) -> ApiResult<Json<Vec<DiscountRuleRow>>> {
+    let Some(id) = params.id else {
+        return_err!(actix_web::error::ErrorBadRequest("Missing required argument \"id\""));
+    };
+    assert_valid_rule(&state, &params).await?;
+    crud::update_discount_rule(&state.db, params.into_inner().into_row(id)).await?;
+
+    let rules = crud::get_discount_rules(&state.db).await?;
+    Ok(Json(rules))
+}
+
+#[post("/api/delete_discount_rule")]
+pub async fn delete_discount_rule(state: Data<AppState>, params: web::Json<DiscountRuleIdJson>) -> ApiResult<Json<Vec<DiscountRuleRow>>> {
+    crud::delete_discount_rule(&state.db, params.id).await?;
+
+    let rules = crud::get_discount_rules(&state.db).await?;
+    Ok(Json(rules))
+}
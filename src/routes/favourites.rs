@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpRequest, get, post, web::{Data, Json}};
+
+use crate::{AppState, database::{crud, model::QuickCartRow}, error::ApiResult, return_err, routes::{products::{BundleInCart, Cart, ProductInCart, TransactionIdJson, checkout}, user_from_cookie}};
+
+const MAX_QUICK_CARTS: u32 = 20;
+const MAX_QUICK_CART_NAME_LENGTH: usize = 50;
+
+#[derive(serde::Deserialize)]
+struct IdJson {
+    id: u32,
+}
+
+#[get("/api/favourites")]
+pub async fn get_favourites(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<Vec<u32>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    Ok(Json(crud::get_favourites(&state.db, user.id).await?))
+}
+
+#[post("/api/favourites/add")]
+pub async fn add_favourite(state: Data<AppState>, req: HttpRequest, body: Json<IdJson>) -> ApiResult<Json<Vec<u32>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    if crud::get_product(&state.db, body.id).await.is_err() {
+        return_err!(actix_web::error::ErrorNotFound("Product not found"));
+    }
+    crud::add_favourite(&state.db, user.id, body.id).await?;
+    Ok(Json(crud::get_favourites(&state.db, user.id).await?))
+}
+
+#[post("/api/favourites/remove")]
+pub async fn remove_favourite(state: Data<AppState>, req: HttpRequest, body: Json<IdJson>) -> ApiResult<Json<Vec<u32>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    crud::remove_favourite(&state.db, user.id, body.id).await?;
+    Ok(Json(crud::get_favourites(&state.db, user.id).await?))
+}
+
+#[get("/api/quick_carts")]
+pub async fn get_quick_carts(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<Vec<QuickCartRow>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    Ok(Json(crud::get_quick_carts(&state.db, user.id).await?))
+}
+
+#[derive(serde::Deserialize)]
+struct SaveQuickCartJson {
+    name: String,
+    cart: Cart,
+}
+
+/// Saves a named cart, replacing the contents of an existing cart with the same name
+#[post("/api/quick_carts/save")]
+pub async fn save_quick_cart(state: Data<AppState>, req: HttpRequest, body: Json<SaveQuickCartJson>) -> ApiResult<Json<QuickCartRow>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_QUICK_CART_NAME_LENGTH {
+        return_err!(actix_web::error::ErrorBadRequest("Quick cart name must be 1-50 characters"));
+    }
+    if body.cart.is_empty() {
+        return_err!(actix_web::error::ErrorBadRequest("Quick cart is empty"));
+    }
+    let exists = crud::get_quick_carts(&state.db, user.id).await?.iter().any(|cart| cart.name == name);
+    if !exists && crud::count_quick_carts(&state.db, user.id).await? >= MAX_QUICK_CARTS {
+        return_err!(actix_web::error::ErrorConflict("Too many quick carts, delete one first"));
+    }
+    Ok(Json(crud::save_quick_cart(&state.db, user.id, name, &body.cart).await?))
+}
+
+#[post("/api/quick_carts/delete")]
+pub async fn delete_quick_cart(state: Data<AppState>, req: HttpRequest, body: Json<IdJson>) -> ApiResult<()> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    if !crud::delete_quick_cart(&state.db, user.id, body.id).await? {
+        return_err!(actix_web::error::ErrorNotFound("Quick cart not found"));
+    }
+    Ok(())
+}
+
+/// Buys a saved cart with the same checks and prices as `/api/buy_products`
+#[post("/api/quick_carts/buy")]
+pub async fn buy_quick_cart(state: Data<AppState>, req: HttpRequest, body: Json<IdJson>) -> ApiResult<Json<TransactionIdJson>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let Some(quick_cart) = crud::get_quick_cart(&state.db, user.id, body.id).await? else {
+        return_err!(actix_web::error::ErrorNotFound("Quick cart not found"));
+    };
+    let transaction_id = checkout(&state, &user, &quick_cart.cart).await?;
+    Ok(Json(TransactionIdJson { transaction_id }))
+}
+
+/// Buys the same products as the user's last purchase at today's prices. Bundles picked by hand are
+/// bought again as bundles, automatic ones apply by themselves and free stamp card rewards are left out.
+/// Private purchases are not linked to the user, so they cannot be bought again
+#[post("/api/buy_again")]
+pub async fn buy_again(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<TransactionIdJson>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let Some(last_purchase) = crud::get_last_purchase(&state.db, user.id).await? else {
+        return_err!(actix_web::error::ErrorNotFound("No earlier purchase to buy again"));
+    };
+    let items = crud::get_transaction_items(&state.db, last_purchase).await?;
+    let bundles = crud::get_bundles(&state.db).await?;
+
+    let mut cart = Cart { products: Vec::new(), bundles: Vec::new(), redeem_stamps: Vec::new() };
+    let mut manual_bundles: HashMap<u32, u32> = HashMap::new();
+    for item in items.iter().filter(|item| item.stamp_program.is_none()) {
+        let manual_bundle = item.bundle
+            .and_then(|id| bundles.iter().find(|bundle| bundle.id == id && !bundle.auto_apply))
+            .and_then(|bundle| bundle.components.iter().find(|c| c.product == item.product).map(|c| (bundle.id, c.quantity)));
+        match manual_bundle {
+            // Each component line holds quantity * bundle count units
+            Some((bundle, per_bundle)) if per_bundle > 0 => {
+                let count = manual_bundles.entry(bundle).or_insert(u32::MAX);
+                *count = (*count).min(item.quantity / per_bundle);
+            },
+            _ => match cart.products.iter_mut().find(|p| p.id == item.product) {
+                Some(product) => product.quantity += item.quantity,
+                None => cart.products.push(ProductInCart { id: item.product, quantity: item.quantity }),
+            },
+        }
+    }
+    cart.bundles = manual_bundles.into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(id, quantity)| BundleInCart { id, quantity })
+        .collect();
+    if cart.is_empty() {
+        return_err!(actix_web::error::ErrorNotFound("No earlier purchase to buy again"));
+    }
+
+    let transaction_id = checkout(&state, &user, &cart).await?;
+    Ok(Json(TransactionIdJson { transaction_id }))
+}
@@ -1,4 +1,3 @@
-pub mod routes;
 pub mod oauth;
 pub mod products;
 pub mod user;
@@ -6,6 +5,17 @@ pub mod stats;
 pub mod debug;
 pub mod payment;
 pub mod transactions;
+pub mod discounts;
+pub mod bundles;
+pub mod stamps;
+pub mod bonuses;
+pub mod vouchers;
+pub mod settlement;
+pub mod cash_box;
+pub mod payouts;
+pub mod spending_limits;
+pub mod notifications;
+pub mod favourites;
 
 use actix_web::{HttpMessage, HttpRequest, HttpResponse, body::BoxBody, dev::{ServiceRequest, ServiceResponse}, middleware, web::Data};
 use sqlx::SqlitePool;
@@ -13,11 +23,12 @@ use sqlx::SqlitePool;
 use crate::{AppState, auth, database::model::UserRow, error::AppError, utils::{self, get_path}};
 
 const LOGIN_PATH: &str = "/login";
-const PATH_WHITELIST: [&str; 4] = [
+const PATH_WHITELIST: [&str; 5] = [
     LOGIN_PATH,
     "/api/auth/google",
     "/api/auth/google/callback",
     payment::swish::CALLBACK_URL,
+    payment::swish::REFUND_CALLBACK_URL,
 ];
 
 //
@@ -66,9 +77,8 @@ pub async fn session_middleware(
                 }
                 // Validation Bad
                 Ok(None) => {
-                    match req.cookie(auth::AUTH_COOKIE) {
-                        Some(mut cookie) => cookie.make_removal(),
-                        None => {},
+                    if let Some(mut cookie) = req.cookie(auth::AUTH_COOKIE) {
+                        cookie.make_removal();
                     }
                     if path.starts_with("/api/") {
                         Err(actix_web::error::ErrorUnauthorized("Could not validate session"))
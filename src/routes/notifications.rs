@@ -0,0 +1,43 @@
+use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::{crud, model::NotificationRow}, error::ApiResult, routes::user_from_cookie};
+
+#[derive(serde::Deserialize)]
+struct NotificationsQuery {
+    #[serde(default)]
+    unread_only: bool,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// The user's notifications, newest first
+#[get("/api/notifications")]
+pub async fn get_notifications(state: Data<AppState>, req: HttpRequest, query: web::Query<NotificationsQuery>) -> ApiResult<Json<Vec<NotificationRow>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let notifications = crud::get_notifications(&state.db, user.id, query.unread_only, limit, query.offset.unwrap_or(0)).await?;
+    Ok(Json(notifications))
+}
+
+#[derive(serde::Serialize)]
+struct UnreadCountResponse {
+    count: u32,
+}
+
+#[get("/api/notifications/unread_count")]
+pub async fn get_unread_count(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<UnreadCountResponse>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    Ok(Json(UnreadCountResponse { count: crud::count_unread_notifications(&state.db, user.id).await? }))
+}
+
+#[derive(serde::Deserialize)]
+struct MarkReadJson {
+    ids: Option<Vec<u32>>, // All notifications if None
+}
+
+#[post("/api/notifications/read")]
+pub async fn mark_read(state: Data<AppState>, req: HttpRequest, body: Json<MarkReadJson>) -> ApiResult<Json<UnreadCountResponse>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    crud::mark_notifications_read(&state.db, user.id, body.ids.as_deref()).await?;
+    Ok(Json(UnreadCountResponse { count: crud::count_unread_notifications(&state.db, user.id).await? }))
+}
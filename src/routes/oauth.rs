@@ -59,10 +59,9 @@ pub async fn google_callback(state: Data<AppState>, req: HttpRequest, query: web
         .send().await.map_err(ClientError::from)?
         .json().await.map_err(ClientError::from)?;
 
-    if let Ok(logged_in_user) = user_from_cookie(&state.db, &req).await {
-        if crud::email_switch_exists(&state.db, logged_in_user.id).await? {
-            crud::finalize_email_switch(&state.db, logged_in_user.id, &user_info.email, &user_info.id).await?;
-        }
+    if let Ok(logged_in_user) = user_from_cookie(&state.db, &req).await
+        && crud::email_switch_exists(&state.db, logged_in_user.id).await? {
+        crud::finalize_email_switch(&state.db, logged_in_user.id, &user_info.email, &user_info.id).await?;
     }
 
     let mut user = crud::get_user(&state.db, None, Some(&user_info.id)).await;
@@ -70,7 +69,7 @@ pub async fn google_callback(state: Data<AppState>, req: HttpRequest, query: web
         user = crud::create_user(&state.db, None, &user_info.email, &user_info.id).await;
     };
 
-    return create_session_response(state, user?.id).await;
+    create_session_response(state, user?.id).await
 }
 
 async fn create_session_response(state: Data<AppState>, user_id: u32) -> ApiResult<HttpResponse> {
@@ -93,11 +92,10 @@ async fn create_session_response(state: Data<AppState>, user_id: u32) -> ApiResu
 
 #[get("/api/auth/logout")]
 pub async fn logout(state: Data<AppState>, req: HttpRequest) -> ApiResult<HttpResponse> {
-    let extensions = req.extensions();
-    let Some(session) = extensions.get::<Session>() else {
+    let Some(session) = req.extensions().get::<Session>().cloned() else {
         return_err!(actix_web::error::ErrorInternalServerError("Could not find session to remove for logged in user"));
     };
-    auth::invalidate_session(&state.db, session).await?;
+    auth::invalidate_session(&state.db, &session).await?;
 
     let Some(mut cookie) = req.cookie(auth::AUTH_COOKIE) else {
         return_err!(actix_web::error::ErrorInternalServerError("Could not find cookie"));
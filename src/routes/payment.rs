@@ -1,15 +1,117 @@
+use actix_web::web::Data;
+use sqlx::SqlitePool;
+use time::UtcDateTime;
+
+use crate::{AppState, database::{crud, model::UserRow}, error::{ApiResult, AppError}, model::{NotificationKind, PendingTransaction, TransactionKind}};
+
+/// How money entered or left the store, stored on deposit and refund ledger entries
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "payment_method", rename_all = "snake_case")]
 pub enum PaymentMethod {
     Swish,
+    Cash,
+}
+
+/// A deposit as seen by any provider
+#[derive(serde::Serialize)]
+pub struct Deposit {
+    pub user: Option<u32>, // None if the user has been deleted
+    pub amount: f32,
+    pub refunded: f32,
+    pub status: swish::Status,
+}
+
+/// A way of putting money into a balance. Deposits are credited once the provider confirms them,
+/// through a callback or poll (Swish) or as soon as they are registered (cash)
+#[allow(async_fn_in_trait)] // Only used through static dispatch
+pub trait PaymentProvider {
+    const METHOD: PaymentMethod;
+    type Id;
+    /// What whoever started the deposit gets back, e.g. the token for opening the Swish app
+    type Started: serde::Serialize;
+    type Refund: serde::Serialize;
+
+    /// Starts a deposit of `amount` for `user`. `issuer` is the user themself or the maintainer taking the money
+    async fn create_deposit(state: &Data<AppState>, user: &UserRow, amount: f32, issuer: &UserRow) -> ApiResult<Self::Started>;
+    async fn get_deposit(state: &Data<AppState>, id: Self::Id) -> ApiResult<Deposit>;
+    /// Pays `amount` of a confirmed deposit back, everything not yet refunded if None
+    async fn refund(state: &Data<AppState>, id: Self::Id, amount: Option<f32>, issuer: &UserRow) -> ApiResult<Self::Refund>;
+}
+
+/// Adds a confirmed deposit to the user's balance, plus the best matching deposit bonus.
+/// The bonus is its own ledger entry sharing the payment reference with the deposit
+pub async fn credit_deposit(pool: &SqlitePool, user_id: u32, amount: f32, method: PaymentMethod, reference: &str) -> Result<(), AppError> {
+    let user = crud::get_user(pool, Some(user_id), None).await?;
+    let now = UtcDateTime::now().unix_timestamp();
+
+    let best_bonus = crud::get_active_deposit_bonus_rules(pool, now).await?.into_iter()
+        .map(|rule| (rule.bonus_for(amount, now), rule))
+        .filter(|(bonus, _)| *bonus > 0.0)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    let mut deposit = PendingTransaction::balance_change(user.id, amount, TransactionKind::Deposit);
+    deposit.payment_reference = Some(reference.to_string());
+    deposit.payment_method = Some(method);
+    let transaction_id = crud::create_transaction(pool, deposit).await?;
+
+    let mut balance = user.balance + amount;
+    let mut message = format!("Your deposit of {amount:.2} kr was credited");
+    if let Some((bonus_amount, rule)) = best_bonus {
+        let mut bonus = PendingTransaction::balance_change(user.id, bonus_amount, TransactionKind::Bonus);
+        bonus.payment_reference = Some(reference.to_string());
+        bonus.note = Some(rule.name);
+        crud::create_transaction(pool, bonus).await?;
+        balance += bonus_amount;
+        message += &format!(" with a bonus of {bonus_amount:.2} kr");
+        log::info!("User {} got a deposit bonus of {} kr from rule {}", user.id, bonus_amount, rule.id);
+    }
+    crud::update_user_balance(pool, user.id, balance).await?;
+    crud::create_notification(pool, user.id, NotificationKind::Deposit, &message, Some(transaction_id)).await?;
+
+    Ok(())
+}
+
+/// Bonuses are taken back in proportion to the refunded share of the deposit
+pub async fn refunded_bonus_share(pool: &SqlitePool, reference: &str, deposit_amount: f32, amount: f32) -> Result<f32, AppError> {
+    let bonus_entries: Vec<f32> = crud::get_payment_transactions(pool, reference).await?.into_iter()
+        .filter(|t| t.kind == TransactionKind::Bonus)
+        .map(|t| t.amount)
+        .collect();
+    let bonus_granted: f32 = bonus_entries.iter().filter(|a| **a > 0.0).sum();
+    let bonus_left: f32 = bonus_entries.iter().sum();
+    Ok((bonus_granted * amount / deposit_amount).min(bonus_left).max(0.0))
+}
+
+/// Takes a refund and the bonus reversed with it out of the user's balance
+pub async fn debit_refund(pool: &SqlitePool, user: &UserRow, amount: f32, bonus_reversed: f32, method: PaymentMethod, reference: &str) -> Result<(), AppError> {
+    let mut refund = PendingTransaction::balance_change(user.id, -amount, TransactionKind::Refund);
+    refund.payment_reference = Some(reference.to_string());
+    refund.payment_method = Some(method);
+    crud::create_transaction(pool, refund).await?;
+    if bonus_reversed > 0.0 {
+        let mut bonus = PendingTransaction::balance_change(user.id, -bonus_reversed, TransactionKind::Bonus);
+        bonus.payment_reference = Some(reference.to_string());
+        bonus.note = Some(String::from("Reversed by refund"));
+        crud::create_transaction(pool, bonus).await?;
+    }
+    crud::update_user_balance(pool, user.id, user.balance - amount - bonus_reversed).await?;
+    Ok(())
 }
 
 pub mod swish {
     use actix_web::{HttpRequest, HttpResponse, get, http::StatusCode, post, web::{self, Data}};
+    use std::net::{IpAddr, SocketAddr};
+
+    use sqlx::SqlitePool;
+    use time::UtcDateTime;
     use uuid::Uuid;
 
-    use crate::{AppState, database::{self, crud, model::SwishPaymentRequestRow}, error::{ApiResult, AppError, ClientError, GenericError, SwishErrorResponse}, model::PendingTransaction, return_err, routes::user_from_cookie};
+    use super::{Deposit, PaymentMethod, PaymentProvider, credit_deposit, debit_refund, refunded_bonus_share};
+    use crate::{AppState, Role, database::{crud, model::{SwishCallbackLogRow, SwishPaymentRequestRow, SwishPaymentSummaryRow, SwishRefundRow, UserRow}}, error::{ApiResult, AppError, ClientError, GenericError, SwishErrorResponse}, model::{PaymentRequestQuery, PendingTransaction, TransactionKind}, return_err, routes::user_from_cookie, utils::{self, QrFormat}};
 
     pub const CALLBACK_URL: &str = "/api/payment/swish/callback"; // If changing URL: Remember to change post function
-    pub const SWISH_QR_CODE_API: &str = "https://mpc.getswish.net/qrg-swish/api/v1/commerce";
+    pub const REFUND_CALLBACK_URL: &str = "/api/payment/swish/refund_callback"; // If changing URL: Remember to change post function
     
     #[derive(serde::Serialize)]
     #[allow(non_snake_case)]
@@ -29,7 +131,7 @@ pub mod swish {
                 payeeAlias: state.env.swish_number.clone(),
                 amount,
                 currency: String::from("SEK"),
-                callbackUrl: String::from(state.env.site_domain.clone() + CALLBACK_URL),
+                callbackUrl: state.env.site_domain.clone() + CALLBACK_URL,
                 message: String::from("Konsfekt Betalning"),
                 callbackIdentifier: Uuid::new_v4().to_string()
             }
@@ -89,6 +191,71 @@ pub mod swish {
         }
     }
 
+    /// Status of a refund, Swish sends these in upper case
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize, sqlx::Type)]
+    #[serde(rename_all = "lowercase")]
+    #[sqlx(type_name = "swish_refund_status", rename_all = "lowercase")]
+    pub enum RefundStatus {
+        Created,
+        Validated,
+        Debited,
+        Paid,
+        Error,
+    }
+
+    impl RefundStatus {
+        /// Paid and Error are never left again
+        pub fn is_final(&self) -> bool {
+            matches!(self, RefundStatus::Paid | RefundStatus::Error)
+        }
+    }
+
+    impl std::str::FromStr for RefundStatus {
+        type Err = GenericError;
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s.to_lowercase().as_str() {
+                "created" => Ok(RefundStatus::Created),
+                "validated" => Ok(RefundStatus::Validated),
+                "debited" => Ok(RefundStatus::Debited),
+                "paid" => Ok(RefundStatus::Paid),
+                "error" => Ok(RefundStatus::Error),
+                unknown => Err(GenericError::new("Could not parse Swish refund status")
+                    .with_status(StatusCode::BAD_REQUEST)
+                    .add_info(format!("Found {unknown}")))
+            }
+        }
+    }
+
+    #[derive(serde::Serialize)]
+    #[allow(non_snake_case)]
+    /// Info we send to Swish to refund a payment
+    pub struct RefundRequestObject {
+        originalPaymentReference: String,
+        callbackUrl: String,
+        payerAlias: String, // Our Swish number, we pay the refund
+        amount: f32,
+        currency: String,
+        message: String,
+        callbackIdentifier: String,
+    }
+
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(non_snake_case, dead_code)]
+    pub struct RefundCallback {
+        id: String,
+        paymentReference: Option<String>,
+        originalPaymentReference: String,
+        payerAlias: String,
+        payeeAlias: Option<String>,
+        amount: f64,
+        currency: String,
+        status: String,
+        dateCreated: String,
+        datePaid: Option<String>,
+        errorCode: Option<String>,
+        errorMessage: Option<String>,
+    }
+
     async fn initiate_payment(state: &Data<AppState>, amount: f32) -> Result<SwishPaymentResponse, AppError> {
         // Our's and Swish's payment identifier
         let payment_id: String = Uuid::new_v4().simple().to_string().to_uppercase(); 
@@ -117,47 +284,224 @@ pub mod swish {
                 _ => Err(GenericError::new("Could not find/parse Swish response token or location").into())
             }
         }
-        Err(SwishErrorResponse::to_error(response).await.into())
+        Err(SwishErrorResponse::to_error(response).await)
     }
 
     #[derive(serde::Deserialize)]
     struct CreatePaymentRequestQuery { amount: f32 }
 
     #[derive(serde::Serialize)]
-    struct CreatePaymentRequestResponse { 
+    pub struct CreatePaymentRequestResponse { 
         payment_id: String,
         token: String
     }
 
+    pub struct Swish;
+
+    impl PaymentProvider for Swish {
+        const METHOD: PaymentMethod = PaymentMethod::Swish;
+        type Id = String;
+        type Started = CreatePaymentRequestResponse;
+        type Refund = SwishRefundRow;
+
+        async fn create_deposit(state: &Data<AppState>, user: &UserRow, amount: f32, _issuer: &UserRow) -> ApiResult<CreatePaymentRequestResponse> {
+            if amount < 30.0 {
+                return_err!(actix_web::error::ErrorBadRequest("amount < 30 kr"));
+            }
+
+            let swish_payment_response = initiate_payment(state, amount).await?;
+            let now = UtcDateTime::now().unix_timestamp();
+            crud::create_payment_request(&state.db, SwishPaymentRequestRow {
+                id: swish_payment_response.payment_id.clone(),
+                user: user.id,
+                amount,
+                status: Status::Pending,
+                token: swish_payment_response.token.clone(),
+                callback_identifier: swish_payment_response.callback_identifier,
+                location: swish_payment_response.location.clone(),
+                payment_reference: None,
+                created_at: now,
+                updated_at: now,
+                paid_at: None,
+                error_code: None,
+                error_message: None,
+            }).await?;
+
+            log::info!("User {} initiated a Swish payment", user.id);
+
+            Ok(CreatePaymentRequestResponse {
+                payment_id: swish_payment_response.payment_id,
+                token: swish_payment_response.token.clone()
+            })
+        }
+
+        async fn get_deposit(state: &Data<AppState>, id: String) -> ApiResult<Deposit> {
+            let payment_request = crud::get_payment_request(&state.db, id.clone()).await?;
+            let refunded = crud::get_refunds(&state.db, id).await?.iter()
+                .filter(|refund| refund.status != RefundStatus::Error)
+                .map(|refund| refund.amount)
+                .sum();
+            Ok(Deposit {
+                user: Some(payment_request.user),
+                amount: payment_request.amount,
+                refunded,
+                status: payment_request.status,
+            })
+        }
+
+        /// The user's balance is debited right away, together with the matching share of any deposit
+        /// bonus, and credited back if Swish reports that the refund failed
+        async fn refund(state: &Data<AppState>, id: String, amount: Option<f32>, issuer: &UserRow) -> ApiResult<SwishRefundRow> {
+            let payment_request = crud::get_payment_request(&state.db, id.clone()).await?;
+            if payment_request.status != Status::Paid {
+                return_err!(actix_web::error::ErrorBadRequest("Only paid payments can be refunded"));
+            }
+            let Some(payment_reference) = payment_request.payment_reference.clone() else {
+                return_err!(actix_web::error::ErrorBadRequest("Payment has no Swish payment reference"));
+            };
+
+            let deposit = Self::get_deposit(state, id).await?;
+            let remaining = deposit.amount - deposit.refunded;
+            let amount = amount.unwrap_or(remaining);
+            if amount <= 0.0 || amount > remaining {
+                return_err!(actix_web::error::ErrorBadRequest("Refund amount must be positive and at most what is left of the payment"));
+            }
+
+            let bonus_reversed = refunded_bonus_share(&state.db, &payment_request.id, payment_request.amount, amount).await?;
+            let user = crud::get_user(&state.db, Some(payment_request.user), None).await?;
+            if user.balance < amount + bonus_reversed {
+                return_err!(actix_web::error::ErrorPaymentRequired("User's balance is too low to refund the payment"));
+            }
+
+            let refund_id: String = Uuid::new_v4().simple().to_string().to_uppercase();
+            let refund_request = RefundRequestObject {
+                originalPaymentReference: payment_reference,
+                callbackUrl: state.env.site_domain.clone() + REFUND_CALLBACK_URL,
+                payerAlias: state.env.swish_number.clone(),
+                amount,
+                currency: String::from("SEK"),
+                message: String::from("Konsfekt Återbetalning"),
+                callbackIdentifier: Uuid::new_v4().to_string(),
+            };
+            let response = state.client
+                .put(format!("{}{}", state.env.swish_refund_url, refund_id))
+                .json(&refund_request)
+                .send().await.map_err(ClientError::from)?;
+            if response.status() != 201 {
+                return Err(SwishErrorResponse::to_error(response).await.into());
+            }
+            let location = response.headers().get("Location").and_then(|l| l.to_str().ok()).map(String::from);
+
+            crud::create_refund(&state.db, SwishRefundRow {
+                id: refund_id.clone(),
+                payment_request: payment_request.id.clone(),
+                user: Some(user.id),
+                amount,
+                bonus_reversed,
+                status: RefundStatus::Created,
+                callback_identifier: refund_request.callbackIdentifier,
+                location,
+                issued_by: Some(issuer.id),
+                created_at: UtcDateTime::now().unix_timestamp(),
+            }).await?;
+
+            debit_refund(&state.db, &user, amount, bonus_reversed, Self::METHOD, &payment_request.id).await?;
+            log::info!("User {} refunded {} kr of payment {} to user {}", issuer.id, amount, payment_request.id, user.id);
+
+            Ok(crud::get_refund(&state.db, refund_id).await?)
+        }
+    }
+
     #[post("/api/payment/swish/create_payment_request")]
     pub async fn create_payment_request(state: Data<AppState>, req: HttpRequest, query: web::Query<CreatePaymentRequestQuery>) -> ApiResult<web::Json<CreatePaymentRequestResponse>> {
         let user = user_from_cookie(&state.db, &req).await?;
+        Ok(web::Json(Swish::create_deposit(&state, &user, query.amount, &user).await?))
+    }
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, sqlx::Type)]
+    #[serde(rename_all = "lowercase")]
+    #[sqlx(type_name = "swish_callback_kind", rename_all = "lowercase")]
+    pub enum CallbackKind {
+        Payment,
+        Refund,
+    }
+
+    /// Longest callback body stored in the callback log, Swish's own callbacks are far shorter
+    const MAX_LOGGED_PAYLOAD: usize = 4096;
 
-        if query.amount < 30.0 {
-            return_err!(actix_web::error::ErrorBadRequest("amount < 30 kr"));
+    /// Where a callback came from. `X-Forwarded-For` is only read for connections from one of
+    /// `SWISH_TRUSTED_PROXIES`, and then only its last entry, which the proxy added itself
+    fn callback_source(state: &Data<AppState>, req: &HttpRequest) -> Option<String> {
+        let peer = req.peer_addr()?.ip();
+        if !state.env.swish_trusted_proxies.iter().any(|net| net.contains(&peer)) {
+            return Some(peer.to_string());
         }
+        let forwarded_for = req.headers().get("X-Forwarded-For")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.rsplit(',').next())
+            .map(|ip| ip.trim().to_string());
+        Some(forwarded_for.unwrap_or_else(|| peer.to_string()))
+    }
 
-        let swish_payment_response = initiate_payment(&state, query.amount).await?;
-        let _ = crud::create_payment_request(&state.db, SwishPaymentRequestRow {
-            id: swish_payment_response.payment_id.clone(),
-            user: user.id,
-            amount: query.amount,
-            status: Status::Pending,
-            token: swish_payment_response.token.clone(),
-            callback_identifier: swish_payment_response.callback_identifier,
-            location: swish_payment_response.location.clone(),
-        }).await?;
+    /// Rejects callbacks from outside `SWISH_CALLBACK_ALLOWED_IPS`, see [`callback_source`]
+    fn assert_allowed_source(state: &Data<AppState>, source_ip: Option<&str>) -> ApiResult<()> {
+        if state.env.swish_callback_allowed_ips.is_empty() {
+            return Ok(());
+        }
+        let ip = source_ip.and_then(|ip| ip.parse::<IpAddr>().ok()
+            .or_else(|| ip.parse::<SocketAddr>().ok().map(|addr| addr.ip())));
+        if !ip.is_some_and(|ip| state.env.swish_callback_allowed_ips.iter().any(|net| net.contains(&ip))) {
+            return_err!(actix_web::error::ErrorForbidden("Callback source not allowed"));
+        }
+        Ok(())
+    }
 
-        log::info!("User {} initiated a Swish payment", user.id);
+    /// Compares what Swish reports against what we asked for, returns what differs
+    fn find_mismatch(state: &Data<AppState>, expected_amount: f32, amount: f64, currency: &str, merchant_alias: &str) -> Option<String> {
+        if (amount - expected_amount as f64).abs() > 0.005 {
+            return Some(format!("amount {amount}, expected {expected_amount}"));
+        }
+        if currency != "SEK" {
+            return Some(format!("currency {currency}, expected SEK"));
+        }
+        if merchant_alias != state.env.swish_number {
+            return Some(format!("merchant alias {merchant_alias}, expected {}", state.env.swish_number));
+        }
+        None
+    }
 
-        Ok(web::Json(CreatePaymentRequestResponse {
-            payment_id: swish_payment_response.payment_id,
-            token: swish_payment_response.token.clone()
-        }))
+    /// Stores the raw callback, cut to [`MAX_LOGGED_PAYLOAD`] bytes, together with why it was rejected,
+    /// if it was. Callbacks older than `SWISH_CALLBACK_LOG_DAYS` are pruned at the same time
+    async fn log_callback(state: &Data<AppState>, kind: CallbackKind, reference: Option<String>, source_ip: Option<String>, body: &[u8], result: &ApiResult<()>) -> ApiResult<()> {
+        let now = UtcDateTime::now().unix_timestamp();
+        crud::create_callback_log(&state.db, SwishCallbackLogRow {
+            id: 0,
+            kind,
+            reference,
+            source_ip,
+            payload: String::from_utf8_lossy(&body[..body.len().min(MAX_LOGGED_PAYLOAD)]).into_owned(),
+            rejection: result.as_ref().err().map(|err| err.to_string()),
+            received_at: now,
+        }).await?;
+        crud::prune_callback_log(&state.db, now - state.env.swish_callback_log_days * 86400).await?;
+        Ok(())
     }
 
     #[post("/api/payment/swish/callback")] // If changing URL: Remember to change CALLBACK_URL
-    pub async fn swish_callback(state: Data<AppState>, req: HttpRequest, callback: web::Json<PaymentCallback>) -> ApiResult<()> {
+    pub async fn swish_callback(state: Data<AppState>, req: HttpRequest, body: web::Bytes) -> ApiResult<()> {
+        let source_ip = callback_source(&state, &req);
+        let callback = serde_json::from_slice::<PaymentCallback>(&body);
+        let reference = callback.as_ref().ok().map(|c| c.id.clone());
+
+        let result = match callback {
+            Ok(callback) => handle_payment_callback(&state, &req, source_ip.as_deref(), callback).await,
+            Err(err) => Err(actix_web::error::ErrorBadRequest(format!("Invalid callback payload: {err}"))),
+        };
+        log_callback(&state, CallbackKind::Payment, reference, source_ip, &body, &result).await?;
+        result
+    }
+
+    async fn handle_payment_callback(state: &Data<AppState>, req: &HttpRequest, source_ip: Option<&str>, callback: PaymentCallback) -> ApiResult<()> {
+        assert_allowed_source(state, source_ip)?;
         let payment_id = callback.id.clone();
         let payment_request = crud::get_payment_request(&state.db, payment_id.clone()).await?;
         let Some(callback_identifer) = req.headers().get("callbackIdentifier").and_then(|ci| ci.to_str().ok()) else {
@@ -166,29 +510,246 @@ pub mod swish {
         if callback_identifer != payment_request.callback_identifier {
             return_err!(actix_web::error::ErrorUnauthorized("Incorrect callback identifer"));
         }
+        if let Some(mismatch) = find_mismatch(state, payment_request.amount, callback.amount, &callback.currency, &callback.payeeAlias) {
+            log::error!("ALERT: Swish callback for payment {payment_id} does not match the request ({mismatch}), nothing was credited");
+            return_err!(actix_web::error::ErrorBadRequest(format!("Callback does not match payment request: {mismatch}")));
+        }
         let payment_status = callback.status.parse::<Status>()?;
-        crud::update_payment_request(&state.db, payment_id.clone(), payment_status).await?;
-        
-        if payment_request.status != payment_status { // If still Status::Pending
-            let user = crud::get_user(&state.db, Some(payment_request.user), None).await?;
-            log::info!("Updated user {}'s payment status to {:?} for payment {}", user.id, payment_status, payment_id);
-            if payment_status == Status::Paid {
-                crud::update_user_balance(&state.db, user.id, user.balance + payment_request.amount).await?;
+        if callback.errorCode.is_some() {
+            crud::set_payment_error(&state.db, payment_id, callback.errorCode, callback.errorMessage).await?;
+        }
+        apply_payment_status(&state.db, &payment_request, payment_status, callback.paymentReference.clone()).await?;
+
+        Ok(())
+    }
 
-                let transaction = PendingTransaction {
-                    user: Some(user.id),
-                    products: Vec::new(),
-                    amount: payment_request.amount,
-                    admin_issued: false
-                };
+    /// Stores a status reported by Swish, crediting the user if the payment just became paid.
+    /// Shared by the callback and the background poller
+    pub async fn apply_payment_status(pool: &SqlitePool, payment_request: &SwishPaymentRequestRow, status: Status, payment_reference: Option<String>) -> Result<(), AppError> {
+        if let Some(payment_reference) = payment_reference {
+            crud::set_payment_reference(pool, payment_request.id.clone(), payment_reference).await?;
+        }
+        if payment_request.status == status || 
+            !crud::update_payment_request(pool, payment_request.id.clone(), payment_request.status, status).await? {
+            return Ok(());
+        }
 
-                database::crud::create_transaction(&state.db, transaction).await?;
+        log::info!("Updated user {}'s payment status to {:?} for payment {}", payment_request.user, status, payment_request.id);
+        if status == Status::Paid {
+            credit_deposit(pool, payment_request.user, payment_request.amount, PaymentMethod::Swish, &payment_request.id).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the request's current status from Swish and applies it. Requests that Swish still
+    /// reports as pending, or does not know of, are cancelled if `abandon` is set. If Swish cannot
+    /// be asked the request stays pending, since the user might have paid it
+    pub async fn poll_payment_request(pool: &SqlitePool, client: &reqwest::Client, payment_request: &SwishPaymentRequestRow, abandon: bool) -> Result<(), AppError> {
+        let response = match client.get(&payment_request.location).send().await {
+            Ok(response) if response.status() == 200 => response,
+            Ok(response) if abandon && response.status() == 404 => {
+                return apply_payment_status(pool, payment_request, Status::Cancelled, None).await;
             }
+            Ok(response) => return Err(SwishErrorResponse::to_error(response).await),
+            Err(err) => return Err(ClientError::from(err).into()),
+        };
+        let current: PaymentCallback = response.json().await.map_err(ClientError::from)?;
+        let status = current.status.parse::<Status>()?;
+        if (current.amount - payment_request.amount as f64).abs() > 0.005 || current.currency != "SEK" {
+            log::error!("ALERT: Swish reports {} {} for payment {}, expected {} SEK, nothing was credited",
+                current.amount, current.currency, payment_request.id, payment_request.amount);
+            return Err(GenericError::new("Swish payment does not match payment request").into());
+        }
+
+        if current.errorCode.is_some() {
+            crud::set_payment_error(pool, payment_request.id.clone(), current.errorCode, current.errorMessage).await?;
+        }
+
+        match status {
+            Status::Pending if abandon => apply_payment_status(pool, payment_request, Status::Cancelled, None).await,
+            status => apply_payment_status(pool, payment_request, status, current.paymentReference).await,
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    struct PaymentHistoryQuery {
+        limit: Option<u32>,
+        #[serde(default)]
+        offset: u32,
+    }
+
+    /// The logged in user's payment requests, newest first
+    #[get("/api/payment/swish/history")]
+    pub async fn get_payment_history(state: Data<AppState>, req: HttpRequest, query: web::Query<PaymentHistoryQuery>) -> ApiResult<web::Json<Vec<SwishPaymentSummaryRow>>> {
+        let user = user_from_cookie(&state.db, &req).await?;
+        let payment_requests = crud::query_payment_requests(&state.db, PaymentRequestQuery {
+            user_ids: vec![user.id],
+            statuses: Vec::new(),
+            created_after: None,
+            created_before: None,
+            payment_reference: None,
+            limit: query.limit.unwrap_or(20).clamp(1, 50),
+            offset: query.offset,
+        }).await?;
+        Ok(web::Json(payment_requests))
+    }
+
+    /// All users' payment requests, filtered for reconciliation
+    #[post("/api/payment/swish/payments")]
+    pub async fn get_payment_requests(state: Data<AppState>, query: web::Json<PaymentRequestQuery>) -> ApiResult<web::Json<Vec<SwishPaymentSummaryRow>>> {
+        let mut query = query.into_inner();
+        query.limit = query.limit.clamp(1, 500);
+        let payment_requests = crud::query_payment_requests(&state.db, query).await?;
+        Ok(web::Json(payment_requests))
+    }
+
+    #[derive(serde::Deserialize)]
+    struct RefundQuery { amount: Option<f32> } // Everything not yet refunded if None
+
+    /// Refunds a paid deposit through Swish, see [`Swish::refund`]
+    #[post("/api/payment/swish/refund/{payment_id}")]
+    pub async fn refund_payment(state: Data<AppState>, req: HttpRequest, path: web::Path<String>, query: web::Query<RefundQuery>) -> ApiResult<web::Json<SwishRefundRow>> {
+        let admin = user_from_cookie(&state.db, &req).await?;
+        if admin.role != Role::Admin {
+            return_err!(actix_web::error::ErrorForbidden("Only admins can refund payments"));
+        }
+        Ok(web::Json(Swish::refund(&state, path.into_inner(), query.amount, &admin).await?))
+    }
+
+    #[post("/api/payment/swish/refund_callback")] // If changing URL: Remember to change REFUND_CALLBACK_URL
+    pub async fn refund_callback(state: Data<AppState>, req: HttpRequest, body: web::Bytes) -> ApiResult<()> {
+        let source_ip = callback_source(&state, &req);
+        let callback = serde_json::from_slice::<RefundCallback>(&body);
+        let reference = callback.as_ref().ok().map(|c| c.id.clone());
+
+        let result = match callback {
+            Ok(callback) => handle_refund_callback(&state, &req, source_ip.as_deref(), callback).await,
+            Err(err) => Err(actix_web::error::ErrorBadRequest(format!("Invalid callback payload: {err}"))),
+        };
+        log_callback(&state, CallbackKind::Refund, reference, source_ip, &body, &result).await?;
+        result
+    }
+
+    async fn handle_refund_callback(state: &Data<AppState>, req: &HttpRequest, source_ip: Option<&str>, callback: RefundCallback) -> ApiResult<()> {
+        assert_allowed_source(state, source_ip)?;
+        let refund = crud::get_refund(&state.db, callback.id.clone()).await?;
+        let Some(callback_identifer) = req.headers().get("callbackIdentifier").and_then(|ci| ci.to_str().ok()) else {
+            return_err!(actix_web::error::ErrorUnauthorized("Callback identifer not found"));
+        };
+        if callback_identifer != refund.callback_identifier {
+            return_err!(actix_web::error::ErrorUnauthorized("Incorrect callback identifer"));
+        }
+        if let Some(mismatch) = find_mismatch(state, refund.amount, callback.amount, &callback.currency, &callback.payerAlias) {
+            log::error!("ALERT: Swish callback for refund {} does not match the refund ({mismatch}), ignoring it", refund.id);
+            return_err!(actix_web::error::ErrorBadRequest(format!("Callback does not match refund: {mismatch}")));
+        }
+        let refund_status = callback.status.parse::<RefundStatus>()?;
+        if refund.status == refund_status {
+            return Ok(());
+        }
+        if refund.status.is_final() {
+            log::warn!("Ignoring {:?} callback for refund {} which is already {:?}", refund_status, refund.id, refund.status);
+            return Ok(());
+        }
+        // Only one of several concurrent callbacks gets to move the refund, and thereby credit the user
+        if !crud::update_refund_status(&state.db, refund.id.clone(), refund.status, refund_status).await? {
+            return Ok(());
+        }
+        log::info!("Updated refund {} of payment {} to {:?}", refund.id, refund.payment_request, refund_status);
+
+        // The balance was debited when the refund was issued, give it back
+        if refund_status == RefundStatus::Error && let Some(user_id) = refund.user {
+            let code = callback.errorCode.clone().unwrap_or_default();
+            log::error!("Swish refund {} failed with {}: {} ({})", refund.id, code,
+                SwishErrorResponse::describe_refund_error(&code), callback.errorMessage.clone().unwrap_or_default());
+
+            let user = crud::get_user(&state.db, Some(user_id), None).await?;
+            let mut restored = PendingTransaction::balance_change(user.id, refund.amount, TransactionKind::Refund);
+            restored.payment_reference = Some(refund.payment_request.clone());
+            restored.payment_method = Some(PaymentMethod::Swish);
+            restored.note = Some(String::from("Refund failed"));
+            crud::create_transaction(&state.db, restored).await?;
+            if refund.bonus_reversed > 0.0 {
+                let mut bonus = PendingTransaction::balance_change(user.id, refund.bonus_reversed, TransactionKind::Bonus);
+                bonus.payment_reference = Some(refund.payment_request.clone());
+                bonus.note = Some(String::from("Refund failed"));
+                crud::create_transaction(&state.db, bonus).await?;
+            }
+            crud::update_user_balance(&state.db, user.id, user.balance + refund.amount + refund.bonus_reversed).await?;
         }
 
         Ok(())
     }
 
+    #[get("/api/payment/swish/refunds/{payment_id}")]
+    pub async fn get_refunds(state: Data<AppState>, req: HttpRequest, path: web::Path<String>) -> ApiResult<web::Json<Vec<SwishRefundRow>>> {
+        let user = user_from_cookie(&state.db, &req).await?;
+        if user.role != Role::Admin {
+            return_err!(actix_web::error::ErrorForbidden("Only admins can see refunds"));
+        }
+        let refunds = crud::get_refunds(&state.db, path.into_inner()).await?;
+        Ok(web::Json(refunds))
+    }
+
+    #[derive(serde::Deserialize)]
+    struct CallbackLogQuery {
+        #[serde(default)]
+        rejected_only: bool,
+        limit: Option<u32>,
+    }
+
+    #[get("/api/payment/swish/callback_log")]
+    pub async fn get_callback_log(state: Data<AppState>, query: web::Query<CallbackLogQuery>) -> ApiResult<web::Json<Vec<SwishCallbackLogRow>>> {
+        let rows = crud::get_callback_log(&state.db, query.rejected_only, query.limit.unwrap_or(100)).await?;
+        Ok(web::Json(rows))
+    }
+
+    #[derive(serde::Serialize)]
+    struct CancelPatch {
+        op: &'static str,
+        path: &'static str,
+        value: &'static str,
+    }
+
+    /// Cancels a pending request with Swish. If Swish refuses, e.g. because the user just paid,
+    /// the request's status is fetched from Swish instead so nothing gets lost
+    #[post("/api/payment/swish/cancel/{payment_id}")]
+    pub async fn cancel_payment_request(state: Data<AppState>, req: HttpRequest, path: web::Path<String>) -> ApiResult<web::Json<PaymentStatusResponse>> {
+        let user = user_from_cookie(&state.db, &req).await?;
+        let payment_request = crud::get_payment_request(&state.db, path.into_inner()).await?;
+        if payment_request.user != user.id && user.role != Role::Admin {
+            return_err!(actix_web::error::ErrorForbidden("Cannot cancel other user's payment"));
+        }
+        if payment_request.status != Status::Pending {
+            return_err!(actix_web::error::ErrorBadRequest("Only pending payments can be cancelled"));
+        }
+
+        let response = state.client
+            .patch(&payment_request.location)
+            .header("Content-Type", "application/json-patch+json")
+            .body(serde_json::to_string(&[CancelPatch { op: "replace", path: "/status", value: "cancelled" }]).unwrap_or_default())
+            .send().await.map_err(ClientError::from)?;
+
+        if response.status() == 200 {
+            apply_payment_status(&state.db, &payment_request, Status::Cancelled, None).await?;
+            log::info!("User {} cancelled payment {}", user.id, payment_request.id);
+        } else {
+            let error = SwishErrorResponse::to_error(response).await;
+            if let Err(poll_error) = poll_payment_request(&state.db, &state.client, &payment_request, false).await {
+                log::warn!("Could not poll Swish payment {}: {poll_error}", payment_request.id);
+            }
+            return Err(error.into());
+        }
+
+        let payment_request = crud::get_payment_request(&state.db, payment_request.id).await?;
+        let owner = crud::get_user(&state.db, Some(payment_request.user), None).await?;
+        Ok(web::Json(PaymentStatusResponse {
+            status: payment_request.status,
+            amount: payment_request.amount,
+            balance: owner.balance,
+        }))
+    }
+
     #[derive(serde::Serialize)]
     struct PaymentStatusResponse {
         status: Status,
@@ -210,31 +771,179 @@ pub mod swish {
         }))
     }
 
-    #[derive(serde::Serialize)]
-    struct QrCodeData {
-        token: String,
-        size: String,
-        format: String,
-        border: String,
+    const QR_SIZE_RANGE: std::ops::RangeInclusive<u32> = 100..=1000;
+    /// Swish app lock flags for prefilled codes, summed for the fields the payer may not change
+    const QR_LOCK_PAYEE: u8 = 1;
+    const QR_LOCK_AMOUNT: u8 = 2;
+    const QR_LOCK_MESSAGE: u8 = 4;
+
+    #[derive(serde::Deserialize)]
+    struct QrCodeQuery {
+        #[serde(default)]
+        format: QrFormat,
+        size: Option<u32>,
+    }
+
+    impl QrCodeQuery {
+        fn render(&self, state: &Data<AppState>, data: &str) -> ApiResult<HttpResponse> {
+            let size = self.size.unwrap_or(state.env.swish_qr_size);
+            if !QR_SIZE_RANGE.contains(&size) {
+                return_err!(actix_web::error::ErrorBadRequest(format!(
+                    "size must be between {} and {}", QR_SIZE_RANGE.start(), QR_SIZE_RANGE.end()
+                )));
+            }
+            let image = utils::render_qr(data, self.format, size)?;
+            Ok(HttpResponse::Ok().content_type(self.format.content_type()).body(image))
+        }
     }
 
+    /// QR code that opens the Swish app on a payment request, `?format=png|svg&size=`
     #[get("/api/payment/qr/{payment_id}")]
-    pub async fn get_qr_code(state: Data<AppState>, req: HttpRequest, path: web::Path<String>) -> ApiResult<HttpResponse> {
+    pub async fn get_qr_code(state: Data<AppState>, req: HttpRequest, path: web::Path<String>, query: web::Query<QrCodeQuery>) -> ApiResult<HttpResponse> {
         let user = user_from_cookie(&state.db, &req).await?;
         let payment_request = crud::get_payment_request(&state.db, path.into_inner()).await?;
         if payment_request.user != user.id {
             return_err!(actix_web::error::ErrorForbidden("Cannot retrieve QR code for other user's payment"));
         }
-        let response = state.client.post(SWISH_QR_CODE_API)
-            .json(&QrCodeData {
-                token: payment_request.token, size: String::from("300"), 
-                format: String::from("png"), border: String::from("0")
-            }).send().await.map_err(ClientError::from)?;
+        query.render(&state, &format!("D{}", payment_request.token))
+    }
+
+    #[derive(serde::Deserialize)]
+    struct PrefilledQrQuery {
+        amount: Option<f32>,
+        message: Option<String>,
+        #[serde(flatten)]
+        qr: QrCodeQuery,
+    }
 
-        if response.status() != 200 {
-            return_err!(actix_web::error::ErrorInternalServerError("Could not retrieve QR code from Swish"));
+    /// Static QR code for Swishing the Konsfekt number directly, prefilled with an amount and a message.
+    /// Such payments are not seen by the system and have to be credited by hand.
+    /// The message defaults to one identifying the user
+    #[get("/api/payment/swish/qr")]
+    pub async fn get_prefilled_qr_code(state: Data<AppState>, req: HttpRequest, query: web::Query<PrefilledQrQuery>) -> ApiResult<HttpResponse> {
+        let user = user_from_cookie(&state.db, &req).await?;
+        let message = query.message.clone().unwrap_or_else(|| format!("Konsfekt #{}", user.id));
+        if message.chars().count() > 50 || message.contains(';') {
+            return_err!(actix_web::error::ErrorBadRequest("message must be at most 50 characters and not contain ';'"));
         }
-        let qr_bytes = response.bytes().await.map_err(ClientError::from)?.to_vec();
-        Ok(HttpResponse::Ok().content_type("image/png").body(qr_bytes))
+        let mut lock = QR_LOCK_PAYEE | QR_LOCK_MESSAGE;
+        let amount = match query.amount {
+            Some(amount) if amount <= 0.0 || !amount.is_finite() => {
+                return_err!(actix_web::error::ErrorBadRequest("amount must be positive"));
+            },
+            Some(amount) => {
+                lock |= QR_LOCK_AMOUNT;
+                format!("{amount:.2}")
+            },
+            None => String::new(),
+        };
+        query.qr.render(&state, &format!("C{};{amount};{message};{lock}", state.env.swish_number))
+    }
+}
+
+/// Cash handed to a maintainer, who registers it as a deposit for the user
+pub mod cash {
+    use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+
+    use super::{Deposit, PaymentMethod, PaymentProvider, credit_deposit, debit_refund, refunded_bonus_share, swish::Status};
+    use crate::{AppState, Role, database::{crud, model::{CashDepositRow, UserRow}}, error::ApiResult, return_err, routes::user_from_cookie};
+
+    /// Payment reference on a cash deposit's ledger entries
+    pub fn reference(id: u32) -> String {
+        format!("CASH-{id}")
+    }
+
+    pub struct Cash;
+
+    impl PaymentProvider for Cash {
+        const METHOD: PaymentMethod = PaymentMethod::Cash;
+        type Id = u32;
+        type Started = CashDepositRow;
+        type Refund = CashDepositRow;
+
+        /// The cash is already in the box, so the deposit is credited right away
+        async fn create_deposit(state: &Data<AppState>, user: &UserRow, amount: f32, issuer: &UserRow) -> ApiResult<CashDepositRow> {
+            if amount <= 0.0 || !amount.is_finite() {
+                return_err!(actix_web::error::ErrorBadRequest("amount must be positive"));
+            }
+            let deposit = crud::create_cash_deposit(&state.db, user.id, amount, issuer.id).await?;
+            credit_deposit(&state.db, user.id, amount, Self::METHOD, &reference(deposit.id)).await?;
+            log::info!("User {} registered a cash deposit of {} kr for user {}", issuer.id, amount, user.id);
+            Ok(deposit)
+        }
+
+        async fn get_deposit(state: &Data<AppState>, id: u32) -> ApiResult<Deposit> {
+            let deposit = crud::get_cash_deposit(&state.db, id).await?;
+            Ok(Deposit {
+                user: deposit.user,
+                amount: deposit.amount,
+                refunded: deposit.refunded,
+                status: Status::Paid,
+            })
+        }
+
+        /// Records cash handed back to the user, debiting their balance and the matching share of any bonus
+        async fn refund(state: &Data<AppState>, id: u32, amount: Option<f32>, issuer: &UserRow) -> ApiResult<CashDepositRow> {
+            let deposit = Self::get_deposit(state, id).await?;
+            let Some(user_id) = deposit.user else {
+                return_err!(actix_web::error::ErrorBadRequest("The depositing user has been deleted"));
+            };
+            let remaining = deposit.amount - deposit.refunded;
+            let amount = amount.unwrap_or(remaining);
+            if amount <= 0.0 || amount > remaining {
+                return_err!(actix_web::error::ErrorBadRequest("Refund amount must be positive and at most what is left of the deposit"));
+            }
+
+            let bonus_reversed = refunded_bonus_share(&state.db, &reference(id), deposit.amount, amount).await?;
+            let user = crud::get_user(&state.db, Some(user_id), None).await?;
+            if user.balance < amount + bonus_reversed {
+                return_err!(actix_web::error::ErrorPaymentRequired("User's balance is too low to refund the deposit"));
+            }
+            debit_refund(&state.db, &user, amount, bonus_reversed, Self::METHOD, &reference(id)).await?;
+            crud::add_cash_refund(&state.db, id, amount).await?;
+            log::info!("User {} handed back {} kr of cash deposit {} to user {}", issuer.id, amount, id, user.id);
+
+            Ok(crud::get_cash_deposit(&state.db, id).await?)
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    struct CashDepositJson {
+        user_id: u32,
+        amount: f32,
+    }
+
+    #[post("/api/payment/cash/deposit")]
+    pub async fn create_cash_deposit(state: Data<AppState>, req: HttpRequest, body: Json<CashDepositJson>) -> ApiResult<Json<CashDepositRow>> {
+        let maintainer = user_from_cookie(&state.db, &req).await?;
+        if body.user_id == maintainer.id && maintainer.role != Role::Admin {
+            return_err!(actix_web::error::ErrorForbidden("Cash deposits to your own account must be registered by an admin"));
+        }
+        let user = crud::get_user(&state.db, Some(body.user_id), None).await?;
+        Ok(Json(Cash::create_deposit(&state, &user, body.amount, &maintainer).await?))
+    }
+
+    #[derive(serde::Deserialize)]
+    struct CashRefundJson {
+        id: u32,
+        amount: Option<f32>, // Everything not yet refunded if None
+    }
+
+    #[post("/api/payment/cash/refund")]
+    pub async fn refund_cash_deposit(state: Data<AppState>, req: HttpRequest, body: Json<CashRefundJson>) -> ApiResult<Json<CashDepositRow>> {
+        let admin = user_from_cookie(&state.db, &req).await?;
+        Ok(Json(Cash::refund(&state, body.id, body.amount, &admin).await?))
+    }
+
+    #[derive(serde::Deserialize)]
+    struct CashDepositsQuery {
+        start: Option<i64>,
+        end: Option<i64>,
+    }
+
+    #[get("/api/payment/cash/deposits")]
+    pub async fn get_cash_deposits(state: Data<AppState>, query: web::Query<CashDepositsQuery>) -> ApiResult<Json<Vec<CashDepositRow>>> {
+        let deposits = crud::get_cash_deposits(&state.db, query.start.unwrap_or(0), query.end.unwrap_or(i64::MAX)).await?;
+        Ok(Json(deposits))
     }
 }
@@ -0,0 +1,141 @@
+use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::{crud, model::{PayoutRow, UserRow}}, error::ApiResult, model::{PayoutStatus, PendingTransaction, TransactionKind}, return_err, routes::{payment::PaymentMethod, user_from_cookie}};
+
+/// Payment reference on a payout's ledger entries
+fn reference(id: u32) -> String {
+    format!("PAYOUT-{id}")
+}
+
+/// Moves money between the balance and the payout hold, negative when the payout is requested
+async fn change_balance(state: &Data<AppState>, user: &UserRow, payout: &PayoutRow, amount: f32, note: &str) -> ApiResult<()> {
+    let mut transaction = PendingTransaction::balance_change(user.id, amount, TransactionKind::Payout);
+    transaction.payment_reference = Some(reference(payout.id));
+    transaction.payment_method = Some(payout.method);
+    transaction.note = Some(note.to_string());
+    crud::create_transaction(&state.db, transaction).await?;
+    crud::update_user_balance(&state.db, user.id, user.balance + amount).await?;
+    Ok(())
+}
+
+/// Gives the held amount back when a payout will not be made
+async fn release_payout(state: &Data<AppState>, payout: &PayoutRow, note: &str) -> ApiResult<()> {
+    if let Some(user_id) = payout.user {
+        let user = crud::get_user(&state.db, Some(user_id), None).await?;
+        change_balance(state, &user, payout, payout.amount, note).await?;
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct PayoutRequestJson {
+    amount: Option<f32>, // The whole balance if None
+    method: PaymentMethod,
+    destination: Option<String>, // Required for Swish
+}
+
+/// Requests the balance to be paid out. The amount is held from the balance right away
+/// so it cannot be spent while a maintainer handles the payout
+#[post("/api/payouts/request")]
+pub async fn request_payout(state: Data<AppState>, req: HttpRequest, body: Json<PayoutRequestJson>) -> ApiResult<Json<PayoutRow>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if crud::get_open_payout(&state.db, user.id).await?.is_some() {
+        return_err!(actix_web::error::ErrorConflict("A payout is already in progress"));
+    }
+    let amount = body.amount.unwrap_or(user.balance);
+    if amount <= 0.0 || !amount.is_finite() || amount > user.balance {
+        return_err!(actix_web::error::ErrorBadRequest("Payout amount must be positive and at most the balance"));
+    }
+    let destination = match body.method {
+        PaymentMethod::Swish => {
+            let number: String = body.destination.unwrap_or_default().chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+            if !(8..=15).contains(&number.len()) || !number.chars().all(|c| c.is_ascii_digit()) {
+                return_err!(actix_web::error::ErrorBadRequest("A phone number to Swish the payout to is required"));
+            }
+            Some(number)
+        },
+        PaymentMethod::Cash => None,
+    };
+
+    let payout = crud::create_payout(&state.db, user.id, amount, body.method, destination).await?;
+    change_balance(&state, &user, &payout, -amount, "Payout requested").await?;
+    log::info!("User {} requested a payout of {} kr", user.id, amount);
+    Ok(Json(payout))
+}
+
+#[derive(serde::Deserialize)]
+struct PayoutIdJson {
+    id: u32,
+    note: Option<String>,
+}
+
+#[post("/api/payouts/cancel")]
+pub async fn cancel_payout(state: Data<AppState>, req: HttpRequest, body: Json<PayoutIdJson>) -> ApiResult<Json<PayoutRow>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let payout = crud::get_payout(&state.db, body.id).await?;
+    if payout.user != Some(user.id) {
+        return_err!(actix_web::error::ErrorForbidden("Cannot cancel other user's payout"));
+    }
+    if !crud::update_payout_status(&state.db, payout.id, PayoutStatus::Requested, PayoutStatus::Cancelled, user.id, None).await? {
+        return_err!(actix_web::error::ErrorConflict("Only payouts not yet approved can be cancelled"));
+    }
+    release_payout(&state, &payout, "Payout cancelled").await?;
+    Ok(Json(crud::get_payout(&state.db, payout.id).await?))
+}
+
+#[post("/api/payouts/approve")]
+pub async fn approve_payout(state: Data<AppState>, req: HttpRequest, body: Json<PayoutIdJson>) -> ApiResult<Json<PayoutRow>> {
+    let maintainer = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if !crud::update_payout_status(&state.db, body.id, PayoutStatus::Requested, PayoutStatus::Approved, maintainer.id, body.note).await? {
+        return_err!(actix_web::error::ErrorConflict("Only requested payouts can be approved"));
+    }
+    Ok(Json(crud::get_payout(&state.db, body.id).await?))
+}
+
+#[post("/api/payouts/reject")]
+pub async fn reject_payout(state: Data<AppState>, req: HttpRequest, body: Json<PayoutIdJson>) -> ApiResult<Json<PayoutRow>> {
+    let maintainer = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    let payout = crud::get_payout(&state.db, body.id).await?;
+    if !payout.status.is_open() ||
+        !crud::update_payout_status(&state.db, payout.id, payout.status, PayoutStatus::Rejected, maintainer.id, body.note).await? {
+        return_err!(actix_web::error::ErrorConflict("Only payouts not yet paid can be rejected"));
+    }
+    release_payout(&state, &payout, "Payout rejected").await?;
+    log::info!("User {} rejected payout {}", maintainer.id, payout.id);
+    Ok(Json(crud::get_payout(&state.db, payout.id).await?))
+}
+
+/// Marks an approved payout as paid by hand. Cash payouts are taken out of the cash box
+#[post("/api/payouts/mark_paid")]
+pub async fn mark_payout_paid(state: Data<AppState>, req: HttpRequest, body: Json<PayoutIdJson>) -> ApiResult<Json<PayoutRow>> {
+    let maintainer = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if !crud::update_payout_status(&state.db, body.id, PayoutStatus::Approved, PayoutStatus::Paid, maintainer.id, body.note).await? {
+        return_err!(actix_web::error::ErrorConflict("Only approved payouts can be marked as paid"));
+    }
+    let payout = crud::get_payout(&state.db, body.id).await?;
+    if payout.method == PaymentMethod::Cash {
+        crud::create_cash_withdrawal(&state.db, payout.amount, format!("Payout {}", payout.id), maintainer.id).await?;
+    }
+    log::info!("User {} paid out {} kr for payout {}", maintainer.id, payout.amount, payout.id);
+    Ok(Json(payout))
+}
+
+#[derive(serde::Deserialize)]
+struct PayoutsQuery {
+    status: Option<PayoutStatus>,
+}
+
+#[get("/api/payouts")]
+pub async fn get_payouts(state: Data<AppState>, query: web::Query<PayoutsQuery>) -> ApiResult<Json<Vec<PayoutRow>>> {
+    Ok(Json(crud::get_payouts(&state.db, None, query.status).await?))
+}
+
+#[get("/api/payouts/mine")]
+pub async fn get_own_payouts(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<Vec<PayoutRow>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    Ok(Json(crud::get_payouts(&state.db, Some(user.id), None).await?))
+}
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+
 use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
 use actix_multipart::form::{json::Json as MpJson, tempfile::TempFile, MultipartForm};
 use sqlx::SqlitePool;
 use time::OffsetDateTime;
 
-use crate::{AppState, Role, database::{self, model::UserRow}, error::ApiResult, model::{PendingTransaction, Product, ProductParams}, return_err, routes::user_from_cookie, utils};
+use crate::{AppState, Role, email::{self, ReceiptLine}, database::{self, model::{ProductPriceRow, ProductPriceTierRow, ProductRow, StampCardProgramRow, UserRow}}, error::ApiResult, model::{Allergen, Bundle, PendingItem, PendingTransaction, PriceTier, Product, ProductParams, TransactionKind}, return_err, routes::{spending_limits, user_from_cookie}, utils};
 
 fn product_assert_permission(product: &Product, user: &UserRow) -> ApiResult<()> {
     if !product.flags.modifiable && user.role != Role::Admin {
@@ -12,14 +14,31 @@ fn product_assert_permission(product: &Product, user: &UserRow) -> ApiResult<()>
     Ok(())
 }
 
+fn product_assert_valid(product: &Product) -> ApiResult<()> {
+    if product.availability.as_ref().is_some_and(|a| !a.is_valid()) {
+        return_err!(actix_web::error::ErrorBadRequest("Invalid availability window"));
+    }
+    Ok(())
+}
+
+fn price_assert_valid(price: f32) -> ApiResult<()> {
+    if price < 0.0 || !price.is_finite() {
+        return_err!(actix_web::error::ErrorBadRequest("Price must be a non-negative number"));
+    }
+    Ok(())
+}
+
+/// Whether the product is out of stock, taken off sale or marked as sold out
+fn is_sold_out(product: &Product) -> bool {
+    product.stock.is_none_or(|s| s <= 0) || product.flags.marked_sold_out
+}
+
 async fn get_product_from_id(pool: &SqlitePool, id: Option<u32>) -> ApiResult<Product> {
     let Some(id) = id else {
         return_err!(actix_web::error::ErrorBadRequest("Missing required argument \"id\""));
     };
     let product_row = database::crud::get_product(pool, id).await?;
-    let product = Product::from_row(product_row)
-        .map_err(|_| actix_web::error::ErrorInternalServerError("Internal database formatting incorrect"))?;
-    Ok(product)
+    Ok(Product::from_row(product_row))
 }
 
 #[derive(MultipartForm)]
@@ -33,20 +52,29 @@ struct ProductAndImageForm {
 struct ProductIdJson { id: u32 }
 
 #[derive(sqlx::FromRow, serde::Serialize, serde::Deserialize)]
-struct TransactionIdJson {
-    transaction_id: u32,
+pub struct TransactionIdJson {
+    pub transaction_id: u32,
 }
 
 #[post("/api/create_product")]
 pub async fn create_product(state: Data<AppState>, MultipartForm(form): MultipartForm<ProductAndImageForm>) -> ApiResult<impl actix_web::Responder> {
-    let product = Product::from_request(form.product.into_inner())
-        .map_err(|_| actix_web::error::ErrorBadRequest("Missing required arguments"))?;
+    let mut params = form.product.into_inner();
+    let price_tiers = params.price_tiers.take();
+    let Some(product) = Product::from_request(params) else {
+        return_err!(actix_web::error::ErrorBadRequest("Missing required arguments"));
+    };
+    product_assert_valid(&product)?;
+    for tier in price_tiers.iter().flatten() {
+        price_assert_valid(tier.price)?;
+    }
     let product_row = database::crud::create_product(&state.db, product.into_row()).await?;
+    if let Some(price_tiers) = price_tiers {
+        database::crud::set_price_tiers(&state.db, product_row.id, price_tiers).await?;
+    }
 
-    if let Some(file) = form.image {
-        if utils::save_img_to_disk(file, &product_row.id.to_string()).is_none() {
-            return_err!(actix_web::error::ErrorInternalServerError("Product image not saved"));
-        }
+    if let Some(file) = form.image
+        && utils::save_img_to_disk(file, &product_row.id.to_string()).is_none() {
+        return_err!(actix_web::error::ErrorInternalServerError("Product image not saved"));
     }
     let products = database::crud::get_products(&state.db).await?;
 
@@ -57,11 +85,18 @@ pub async fn create_product(state: Data<AppState>, MultipartForm(form): Multipar
 pub async fn update_product(state: Data<AppState>, req: HttpRequest, MultipartForm(form): MultipartForm<ProductAndImageForm>) -> ApiResult<impl actix_web::Responder> {
     let user = user_from_cookie(&state.db, &req).await?;
     let mut product = get_product_from_id(&state.db, form.product.id).await?;
-    let params = form.product.into_inner();
+    let mut params = form.product.into_inner();
+    let price_tiers = params.price_tiers.take();
 
     product_assert_permission(&product, &user)?;
 
+    let old_price = product.price;
+    let was_sold_out = is_sold_out(&product);
     product.update(params);
+    product_assert_valid(&product)?;
+    for tier in price_tiers.iter().flatten() {
+        price_assert_valid(tier.price)?;
+    }
 
     // Remove marked as sold if restocked
     if product.stock.is_some_and(|s| s > 0) || product.stock.is_none() {
@@ -69,13 +104,25 @@ pub async fn update_product(state: Data<AppState>, req: HttpRequest, MultipartFo
     }
 
     database::crud::update_product_data(&state.db, product.clone().into_row()).await?;
-
-    if let Some(file) = form.image {
-        if utils::save_img_to_disk(file, &product.id.to_string()).is_none() {
-            return_err!(actix_web::error::ErrorInternalServerError("Product image not saved"));
+    if product.price != old_price {
+        database::crud::create_product_price(&state.db, product.id, product.price, OffsetDateTime::now_utc().unix_timestamp()).await?;
+    }
+    if let Some(price_tiers) = price_tiers {
+        database::crud::set_price_tiers(&state.db, product.id, price_tiers).await?;
+    }
+    if was_sold_out && !is_sold_out(&product) {
+        let message = format!("{} is back in stock", product.name);
+        let notified = database::crud::notify_favourite_restocked(&state.db, product.id, &message).await?;
+        if notified > 0 {
+            log::info!("Notified {notified} user(s) of product {} being restocked", product.id);
         }
     }
 
+    if let Some(file) = form.image
+        && utils::save_img_to_disk(file, &product.id.to_string()).is_none() {
+        return_err!(actix_web::error::ErrorInternalServerError("Product image not saved"));
+    }
+
     let products = database::crud::get_products(&state.db).await?;
 
     Ok(web::Json(products))
@@ -109,89 +156,362 @@ pub async fn delete_product(state: Data<AppState>, req: HttpRequest, params: web
     Ok(web::Json(products))
 }
 
-#[get("/api/get_products")]
-pub async fn get_products(state: Data<AppState>) -> ApiResult<impl actix_web::Responder> {
-    let products = database::crud::get_products(&state.db).await?;
-    Ok(web::Json(products))
+#[derive(serde::Deserialize)]
+struct ProductFilterQuery {
+    /// Comma separated allergens, e.g. `nuts,gluten`
+    exclude_allergens: Option<String>,
+    /// Only products that can be bought right now. Always true for users below maintainer
+    only_available: Option<bool>,
 }
 
-#[post("/api/buy_single_product")]
-pub async fn buy_single_product(state: Data<AppState>, req: HttpRequest, product: web::Json<ProductIdJson>) -> ApiResult<Json<TransactionIdJson>> {
+#[get("/api/get_products")]
+pub async fn get_products(state: Data<AppState>, req: HttpRequest, query: web::Query<ProductFilterQuery>) -> ApiResult<impl actix_web::Responder> {
     let user = user_from_cookie(&state.db, &req).await?;
-    let product = database::crud::get_product(&state.db, product.id).await?;
+    let excluded = match &query.exclude_allergens {
+        Some(allergens) => allergens.split(',')
+            .filter(|a| !a.trim().is_empty())
+            .map(str::parse::<Allergen>)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
 
-    if product.stock.is_none() {
-        return_err!(actix_web::error::ErrorNotFound("Product not available"));
-    }
-    if product.price > user.balance {
-        return_err!(actix_web::error::ErrorPaymentRequired("Not enough funds"));
+    let mut products = database::crud::get_products(&state.db).await?;
+    products.retain(|p| !excluded.iter().any(|a| p.allergens.contains(*a)));
+
+    if user.role < Role::Maintainer || query.only_available.unwrap_or(false) {
+        let now = utils::now_local(state.env.timezone);
+        products.retain(|p| p.is_available_at(now));
     }
 
-    let transaction = PendingTransaction {
-        user: match user.private_transactions {
-            true => None,
-            false => Some(user.id)
-        },
-        products: vec![(product.clone(), 1)],
-        amount: -product.price,
-        admin_issued: false
-    };
+    // Show users the price they will be charged
+    if user.role < Role::Maintainer {
+        let tiers = database::crud::get_all_price_tiers(&state.db).await?;
+        for product in &mut products {
+            let product_tiers: Vec<_> = tiers.iter().filter(|t| t.product == product.id).cloned().collect();
+            if let Some((_, price)) = PriceTier::resolve(&user, &product_tiers) {
+                product.price = price;
+            }
+        }
+    }
 
-    let transaction_id = database::crud::create_transaction(&state.db, transaction).await?;
-    database::crud::update_user_balance(&state.db, user.id, user.balance - product.price).await?;
+    Ok(web::Json(products))
+}
 
-    let new_stock = Some(product.stock.unwrap() - 1 as i32);
-    database::crud::update_product_stock(&state.db, product.id, new_stock).await?;
+#[get("/api/get_price_history/{product_id}")]
+pub async fn get_price_history(state: Data<AppState>, path: web::Path<u32>) -> ApiResult<Json<Vec<ProductPriceRow>>> {
+    let history = database::crud::get_price_history(&state.db, path.into_inner()).await?;
+    Ok(Json(history))
+}
 
-    Ok(Json(TransactionIdJson { transaction_id }))
+#[get("/api/get_price_tiers/{product_id}")]
+pub async fn get_price_tiers(state: Data<AppState>, path: web::Path<u32>) -> ApiResult<Json<Vec<ProductPriceTierRow>>> {
+    let tiers = database::crud::get_price_tiers(&state.db, path.into_inner()).await?;
+    Ok(Json(tiers))
 }
 
 #[derive(serde::Deserialize)]
-struct Cart {
-    products: Vec<ProductInCart>
+struct SchedulePriceParams {
+    product_id: u32,
+    price: f32,
+    effective_from: i64, // UNIX timestamp
 }
 
-#[derive(serde::Deserialize)]
-struct ProductInCart {
-    id: u32,
-    quantity: u32,
+#[post("/api/schedule_price")]
+pub async fn schedule_price(state: Data<AppState>, req: HttpRequest, params: web::Json<SchedulePriceParams>) -> ApiResult<Json<ProductPriceRow>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let product = get_product_from_id(&state.db, Some(params.product_id)).await?;
+
+    product_assert_permission(&product, &user)?;
+    price_assert_valid(params.price)?;
+
+    if params.effective_from < OffsetDateTime::now_utc().unix_timestamp() {
+        return_err!(actix_web::error::ErrorBadRequest("Cannot schedule a price in the past"));
+    }
+
+    let price = database::crud::create_product_price(&state.db, product.id, params.price, params.effective_from).await?;
+
+    Ok(Json(price))
 }
 
-#[post("/api/buy_products")]
-pub async fn buy_products(state: Data<AppState>, req: HttpRequest, cart: web::Json<Cart>) -> ApiResult<()> {
+#[derive(serde::Deserialize)]
+struct PriceIdJson { id: u32 }
+
+#[post("/api/cancel_scheduled_price")]
+pub async fn cancel_scheduled_price(state: Data<AppState>, req: HttpRequest, params: web::Json<PriceIdJson>) -> ApiResult<()> {
     let user = user_from_cookie(&state.db, &req).await?;
-    let mut products = Vec::new();
+    let price = database::crud::get_product_price(&state.db, params.id).await?;
+    let product = get_product_from_id(&state.db, Some(price.product)).await?;
+
+    product_assert_permission(&product, &user)?;
+
+    if price.effective_from <= OffsetDateTime::now_utc().unix_timestamp() {
+        return_err!(actix_web::error::ErrorConflict("Price has already taken effect"));
+    }
+
+    database::crud::delete_product_price(&state.db, price.id).await?;
+
+    Ok(())
+}
+
+/// Makes one unit of the most expensive product in `items` qualifying for `program` free.
+/// Returns false if no product qualifies
+fn redeem_reward(items: &mut Vec<PendingItem>, program: &StampCardProgramRow) -> bool {
+    let Some(index) = items.iter()
+        .enumerate()
+        .filter(|(_, item)| item.bundle.is_none() && item.stamp_program.is_none() && program.target.matches(&item.product))
+        .max_by(|(_, a), (_, b)| a.product.price.total_cmp(&b.product.price))
+        .map(|(index, _)| index) else {
+        return false;
+    };
+
+    let item = &mut items[index];
+    let mut reward = PendingItem::new(item.product.clone(), 1);
+    reward.discount = item.product.price;
+    reward.price_tier = item.price_tier;
+    reward.stamp_program = Some(program.id);
+
+    item.quantity -= 1;
+    if item.quantity == 0 {
+        items.remove(index);
+    }
+    items.push(reward);
+    true
+}
+
+/// Prices the cart, charges the user and updates stock. Returns the transaction id
+pub async fn checkout(state: &Data<AppState>, user: &UserRow, cart: &Cart) -> ApiResult<u32> {
+    let now = utils::now_local(state.env.timezone);
+    let rules = database::crud::get_active_discount_rules(&state.db, now.unix_timestamp()).await?;
+    let bundles = database::crud::get_bundles(&state.db).await?;
+
+    // Quantities of products bought outside of bundles, in cart order
+    let mut loose: Vec<(u32, u32)> = Vec::new();
     for p in &cart.products {
-        let product = database::crud::get_product(&state.db, p.id).await?;
+        match loose.iter_mut().find(|(id, _)| *id == p.id) {
+            Some((_, quantity)) => *quantity += p.quantity,
+            None => loose.push((p.id, p.quantity)),
+        }
+    }
+
+    let mut bundle_counts: Vec<(&Bundle, u32)> = Vec::new();
+    for b in cart.bundles.iter().filter(|b| b.quantity > 0) {
+        let Some(bundle) = bundles.iter().find(|bundle| bundle.id == b.id && bundle.active) else {
+            return_err!(actix_web::error::ErrorNotFound("Bundle not available"));
+        };
+        bundle_counts.push((bundle, b.quantity));
+    }
+
+    let mut products: HashMap<u32, ProductRow> = HashMap::new();
+    let mut tiers_used: HashMap<u32, PriceTier> = HashMap::new();
+    let product_ids = loose.iter().map(|(id, _)| *id)
+        .chain(bundle_counts.iter().flat_map(|(bundle, _)| bundle.components.iter().map(|c| c.product)));
+    for id in product_ids {
+        if products.contains_key(&id) {
+            continue;
+        }
+        let mut product = database::crud::get_product(&state.db, id).await?;
         if product.stock.is_none() {
             return_err!(actix_web::error::ErrorNotFound("Product not available"));
         }
-        products.push((product, p.quantity));
+        if !product.is_available_at(now) {
+            return_err!(actix_web::error::ErrorConflict("Product not available at this time"));
+        }
+        let tiers = database::crud::get_price_tiers(&state.db, id).await?;
+        if let Some((tier, price)) = PriceTier::resolve(user, &tiers) {
+            product.price = price;
+            tiers_used.insert(id, tier);
+        }
+        products.insert(id, product);
+    }
+
+    // Apply the automatic bundles giving the largest saving first
+    let mut loose_quantities: HashMap<u32, u32> = loose.iter().copied().collect();
+    let mut auto_bundles: Vec<(&Bundle, f32)> = bundles.iter()
+        .filter(|bundle| bundle.active && bundle.auto_apply)
+        .filter_map(|bundle| bundle.full_price(&products).map(|full| (bundle, full - bundle.price)))
+        .filter(|(_, saving)| *saving > 0.0)
+        .collect();
+    auto_bundles.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    for (bundle, _) in auto_bundles {
+        let count = bundle.fits(&loose_quantities);
+        if count == 0 {
+            continue;
+        }
+        for component in &bundle.components {
+            if let Some(quantity) = loose_quantities.get_mut(&component.product) {
+                *quantity -= component.quantity * count;
+            }
+        }
+        bundle_counts.push((bundle, count));
     }
-    let total_price = products.iter().fold(0.0, |tot, (p, quantity)| tot + p.price * (*quantity as f32));
+
+    let mut items = Vec::new();
+    for (bundle, count) in bundle_counts {
+        items.extend(bundle.pending_items(count, &products));
+    }
+    for (id, _) in loose {
+        let quantity = loose_quantities[&id];
+        if quantity == 0 {
+            continue;
+        }
+        let mut item = PendingItem::new(products[&id].clone(), quantity);
+        item.apply_best_discount(&rules, now);
+        items.push(item);
+    }
+    for item in &mut items {
+        item.price_tier = tiers_used.get(&item.product.id).copied();
+    }
+
+    let programs: Vec<StampCardProgramRow> = database::crud::get_stamp_programs(&state.db).await?
+        .into_iter().filter(|p| p.active).collect();
+    let mut stamp_totals = database::crud::get_stamp_totals(&state.db, user.id).await?;
+    let mut redeemed: Vec<&StampCardProgramRow> = Vec::new();
+    for program_id in &cart.redeem_stamps {
+        let Some(program) = programs.iter().find(|p| p.id == *program_id) else {
+            return_err!(actix_web::error::ErrorNotFound("Stamp card not available"));
+        };
+        let Some((_, total)) = stamp_totals.iter_mut().find(|(id, _)| id == program_id)
+            .filter(|(_, total)| *total >= program.stamps_required as i64) else {
+            return_err!(actix_web::error::ErrorConflict("Not enough stamps"));
+        };
+        if !redeem_reward(&mut items, program) {
+            return_err!(actix_web::error::ErrorBadRequest("No product in cart qualifies for the stamp card reward"));
+        }
+        *total -= program.stamps_required as i64;
+        redeemed.push(program);
+    }
+
+    let total_price = items.iter().fold(0.0, |tot, item| tot + item.total());
 
     if total_price > user.balance {
         return_err!(actix_web::error::ErrorPaymentRequired("Not enough funds"));
     }
+    let budget = spending_limits::spending_budget(state, user.id).await?;
+    if let Some(budget) = &budget {
+        if let Some(remaining) = budget.daily_remaining.filter(|remaining| total_price > remaining + 0.005) {
+            return_err!(actix_web::error::ErrorForbidden(format!("Daily spending limit reached, {remaining:.2} kr left today")));
+        }
+        if let Some(remaining) = budget.weekly_remaining.filter(|remaining| total_price > remaining + 0.005) {
+            return_err!(actix_web::error::ErrorForbidden(format!("Weekly spending limit reached, {remaining:.2} kr left this week")));
+        }
+    }
+
+    let mut sold: HashMap<u32, i32> = HashMap::new();
+    for item in &items {
+        *sold.entry(item.product.id).or_default() += item.quantity as i32;
+    }
+    let receipt_lines: Vec<ReceiptLine> = match user.email_receipts {
+        true => items.iter().map(|item| ReceiptLine { name: item.product.name.clone(), quantity: item.quantity, total: item.total() }).collect(),
+        false => Vec::new(),
+    };
+
+    // The most specific tier that priced any item, None if only base prices were charged
+    let price_tier = PriceTier::candidates(user).into_iter()
+        .find(|tier| items.iter().any(|item| item.price_tier == Some(*tier)));
 
     let transaction = PendingTransaction {
         user: match user.private_transactions {
             true => None,
             false => Some(user.id)
         },
-        products: products.clone(),
+        products: items,
         amount: -total_price,
-        admin_issued: false
+        admin_issued: false,
+        price_tier,
+        kind: TransactionKind::Purchase,
+        payment_reference: None,
+        note: None,
+        payment_method: None,
     };
 
-    database::crud::create_transaction(&state.db, transaction).await?;
+    let earned: Vec<(u32, i64)> = programs.iter()
+        .map(|program| {
+            let stamps = transaction.products.iter()
+                .filter(|item| item.stamp_program.is_none() && program.target.matches(&item.product))
+                .map(|item| item.quantity as i64)
+                .sum();
+            (program.id, stamps)
+        })
+        .filter(|(_, stamps)| *stamps > 0)
+        .collect();
+
+    let transaction_id = database::crud::create_transaction(&state.db, transaction).await?;
     database::crud::update_user_balance(&state.db, user.id, user.balance - total_price).await?;
+    if budget.is_some() {
+        database::crud::add_spending(&state.db, user.id, total_price).await?;
+    }
 
-    for (product, quantity) in products {
-        let new_stock = Some(product.stock.unwrap() - quantity as i32);
-        database::crud::update_product_stock(&state.db, product.id, new_stock).await?;
+    let new_balance = user.balance - total_price;
+    if user.email_receipts {
+        let (subject, body) = email::receipt(transaction_id, &receipt_lines, total_price, new_balance);
+        state.mailer.send_in_background(user.email.clone(), subject, body);
+    }
+    // Only alert on the purchase crossing the threshold, not on every purchase below it
+    if user.low_balance_alerts && user.balance >= user.low_balance_threshold && new_balance < user.low_balance_threshold {
+        let (subject, body) = email::low_balance(new_balance, user.low_balance_threshold, &state.env.site_domain);
+        state.mailer.send_in_background(user.email.clone(), subject, body);
     }
 
+    // Stamps of private purchases are not linked to the transaction either. Those purchases cannot
+    // be undone, so their stamps never have to be taken back
+    let stamp_transaction = (!user.private_transactions).then_some(transaction_id);
+    for program in redeemed {
+        database::crud::add_stamps(&state.db, user.id, program.id, stamp_transaction, -(program.stamps_required as i64)).await?;
+    }
+    for (program_id, stamps) in earned {
+        database::crud::add_stamps(&state.db, user.id, program_id, stamp_transaction, stamps).await?;
+    }
+
+    for (product_id, quantity) in sold {
+        let new_stock = products[&product_id].stock.unwrap() - quantity;
+        database::crud::update_product_stock(&state.db, product_id, Some(new_stock)).await?;
+    }
+
+    Ok(transaction_id)
+}
+
+#[post("/api/buy_single_product")]
+pub async fn buy_single_product(state: Data<AppState>, req: HttpRequest, product: web::Json<ProductIdJson>) -> ApiResult<Json<TransactionIdJson>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let cart = Cart { products: vec![ProductInCart { id: product.id, quantity: 1 }], bundles: Vec::new(), redeem_stamps: Vec::new() };
+    let transaction_id = checkout(&state, &user, &cart).await?;
+
+    Ok(Json(TransactionIdJson { transaction_id }))
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Cart {
+    pub products: Vec<ProductInCart>,
+    #[serde(default)]
+    pub bundles: Vec<BundleInCart>,
+    /// Stamp card programs to redeem a reward from
+    #[serde(default)]
+    pub redeem_stamps: Vec<u32>,
+}
+
+impl Cart {
+    pub fn is_empty(&self) -> bool {
+        self.products.iter().all(|p| p.quantity == 0) && self.bundles.iter().all(|b| b.quantity == 0)
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ProductInCart {
+    pub id: u32,
+    pub quantity: u32,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct BundleInCart {
+    pub id: u32,
+    pub quantity: u32,
+}
+
+#[post("/api/buy_products")]
+pub async fn buy_products(state: Data<AppState>, req: HttpRequest, cart: web::Json<Cart>) -> ApiResult<()> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    checkout(&state, &user, &cart).await?;
+
     Ok(())
 }
 
@@ -203,12 +523,16 @@ pub async fn undo_transaction(state: Data<AppState>, req: HttpRequest, transacti
     if user.id != transaction.user {
         return_err!(actix_web::error::ErrorForbidden("Cannot undo another user's transaction"));
     }
+    if transaction.kind != TransactionKind::Purchase {
+        return_err!(actix_web::error::ErrorConflict("Only purchases can be undone"));
+    }
     if OffsetDateTime::now_utc().unix_timestamp() - transaction.datetime > 60 {
         return_err!(actix_web::error::ErrorConflict("Transaction cannot be undone anymore"));
     }
 
     database::crud::update_user_balance(&state.db, user.id, user.balance + transaction.amount.abs()).await?;
     database::crud::delete_transaction(&state.db, transaction_id.transaction_id).await?;
+    database::crud::remove_spending(&state.db, user.id, transaction.amount.abs(), transaction.datetime).await?;
 
     Ok(())
 }
@@ -1,8 +0,0 @@
-
-
-
-//
-//      Debug
-//
-
-
@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_multipart::form::{json::Json as MpJson, tempfile::TempFile, MultipartForm};
+use actix_web::{post, web::{Data, Json}};
+use time::{Date, Duration, macros::format_description};
+
+use crate::{AppState, database::{crud, model::SettlementPaymentRow}, error::ApiResult, model::{SettlementEntry, SettlementIgnoredLine, SettlementOptions, SettlementReport}, return_err, routes::payment::swish::Status, utils::local_midnight};
+
+const REFERENCE_COLUMNS: [&str; 5] = ["betalningsreferens", "swish-referens", "payment reference", "paymentreference", "referens"];
+const AMOUNT_COLUMNS: [&str; 3] = ["belopp", "amount", "summa"];
+const DATE_COLUMNS: [&str; 5] = ["bokföringsdag", "transaktionsdatum", "datum", "booking date", "date"];
+
+#[derive(MultipartForm)]
+struct SettlementForm {
+    #[multipart(limit = "10MB")]
+    report: TempFile,
+    options: Option<MpJson<SettlementOptions>>,
+}
+
+/// A payment line read from the report
+struct BankLine {
+    line: u64,
+    reference: String,
+    amount: f32,
+}
+
+struct ParsedReport {
+    lines: Vec<BankLine>,
+    ignored: Vec<SettlementIgnoredLine>,
+    first_date: Option<Date>,
+    last_date: Option<Date>,
+}
+
+/// Bank exports are often Windows-1252 rather than UTF-8, read those byte by byte as Latin-1
+fn decode_report(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|b| *b as char).collect(),
+    }
+}
+
+/// Accepts `1 234,50`, `1234.50`, `-30,00 kr` and the like
+fn parse_amount(value: &str) -> Option<f32> {
+    let cleaned: String = value.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_alphabetic())
+        .collect();
+    let cleaned = match cleaned.contains(',') {
+        true => cleaned.replace('.', "").replace(',', "."),
+        false => cleaned,
+    };
+    cleaned.parse().ok()
+}
+
+fn parse_date(value: &str) -> Option<Date> {
+    let value = value.trim();
+    Date::parse(value.get(..10)?, format_description!("[year]-[month]-[day]")).ok()
+}
+
+fn find_column(header: &csv::StringRecord, given: &Option<String>, aliases: &[&str]) -> Option<usize> {
+    let normalized: Vec<String> = header.iter().map(|h| h.trim().to_lowercase()).collect();
+    match given {
+        Some(name) => normalized.iter().position(|h| *h == name.trim().to_lowercase()),
+        None => aliases.iter().find_map(|alias| normalized.iter().position(|h| h == alias)),
+    }
+}
+
+/// Skips any preamble above the header row, which is the first row naming both a reference and an amount column
+fn parse_report(text: &str, options: &SettlementOptions) -> ApiResult<ParsedReport> {
+    let delimiter = match text.matches(';').count() > text.matches(',').count() {
+        true => b';',
+        false => b',',
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let mut columns = None;
+    let mut report = ParsedReport { lines: Vec::new(), ignored: Vec::new(), first_date: None, last_date: None };
+    let mut seen = HashSet::new();
+    for record in reader.records() {
+        let record = record.map_err(|err| actix_web::error::ErrorBadRequest(format!("Could not read report: {err}")))?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        let Some((reference_column, amount_column, date_column)) = columns else {
+            let reference_column = find_column(&record, &options.reference_column, &REFERENCE_COLUMNS);
+            let amount_column = find_column(&record, &options.amount_column, &AMOUNT_COLUMNS);
+            if let (Some(reference_column), Some(amount_column)) = (reference_column, amount_column) {
+                columns = Some((reference_column, amount_column, find_column(&record, &options.date_column, &DATE_COLUMNS)));
+            }
+            continue;
+        };
+
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let reference = record.get(reference_column).unwrap_or_default().trim().to_string();
+        let Some(amount) = record.get(amount_column).and_then(parse_amount) else {
+            report.ignored.push(SettlementIgnoredLine { line, reason: String::from("No amount") });
+            continue;
+        };
+        if reference.is_empty() {
+            report.ignored.push(SettlementIgnoredLine { line, reason: String::from("No payment reference") });
+            continue;
+        }
+        if amount < 0.0 {
+            report.ignored.push(SettlementIgnoredLine { line, reason: format!("Refund of {amount} kr") });
+            continue;
+        }
+        if !seen.insert(reference.clone()) {
+            report.ignored.push(SettlementIgnoredLine { line, reason: format!("Duplicate payment reference {reference}") });
+            continue;
+        }
+        if let Some(date) = date_column.and_then(|column| record.get(column)).and_then(parse_date) {
+            report.first_date = Some(report.first_date.map_or(date, |first| first.min(date)));
+            report.last_date = Some(report.last_date.map_or(date, |last| last.max(date)));
+        }
+        report.lines.push(BankLine { line, reference, amount });
+    }
+
+    if columns.is_none() {
+        return_err!(actix_web::error::ErrorBadRequest("Could not find the payment reference and amount columns"));
+    }
+    Ok(report)
+}
+
+fn amounts_equal(a: f32, b: f32) -> bool {
+    (a - b).abs() < 0.005
+}
+
+/// Compares a Swish settlement report from the bank with the payments Konsfekt credited. Payments paid
+/// close to midnight may be booked on the next day, pass `start` and `end` to widen the period if needed
+#[post("/api/payment/swish/reconcile")]
+pub async fn reconcile_settlement(state: Data<AppState>, MultipartForm(form): MultipartForm<SettlementForm>) -> ApiResult<Json<SettlementReport>> {
+    let options = form.options.map(|o| o.into_inner()).unwrap_or_default();
+    let bytes = std::fs::read(form.report.file.path())
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Could not read uploaded report"))?;
+    let parsed = parse_report(&decode_report(&bytes), &options)?;
+
+    let start = options.start.or(parsed.first_date.map(|date| local_midnight(date, state.env.timezone)));
+    let end = options.end.or(parsed.last_date.map(|date| local_midnight(date + Duration::days(1), state.env.timezone)));
+    let (Some(start), Some(end)) = (start, end) else {
+        return_err!(actix_web::error::ErrorBadRequest("No booking dates found in the report, specify start and end"));
+    };
+
+    let references: Vec<String> = parsed.lines.iter().map(|line| line.reference.clone()).collect();
+    let mut payments: HashMap<String, SettlementPaymentRow> = crud::get_settlement_payments(&state.db, &references, start, end).await?
+        .into_iter()
+        .map(|payment| (payment.payment_reference.clone(), payment))
+        .collect();
+    let system_total = payments.values()
+        .filter(|p| p.status == Status::Paid && p.paid_at.is_some_and(|paid_at| (start..end).contains(&paid_at)))
+        .map(|p| p.amount)
+        .sum();
+
+    let mut report = SettlementReport {
+        start,
+        end,
+        bank_total: parsed.lines.iter().map(|line| line.amount).sum(),
+        system_total,
+        matched: Vec::new(),
+        mismatched: Vec::new(),
+        missing_in_system: Vec::new(),
+        missing_in_bank: Vec::new(),
+        ignored: parsed.ignored,
+    };
+
+    for line in parsed.lines {
+        let payment = payments.remove(&line.reference);
+        let entry = SettlementEntry {
+            payment_reference: line.reference,
+            line: Some(line.line),
+            bank_amount: Some(line.amount),
+            payment: payment.clone(),
+        };
+        match payment {
+            None => report.missing_in_system.push(entry),
+            Some(p) if p.status == Status::Paid && amounts_equal(p.amount, line.amount) && amounts_equal(p.credited, line.amount) => {
+                report.matched.push(entry)
+            },
+            Some(_) => report.mismatched.push(entry),
+        }
+    }
+
+    // Whatever is left was only fetched for being paid within the period
+    let mut missing_in_bank: Vec<SettlementPaymentRow> = payments.into_values().collect();
+    missing_in_bank.sort_by_key(|p| p.paid_at);
+    report.missing_in_bank = missing_in_bank.into_iter().map(|payment| SettlementEntry {
+        payment_reference: payment.payment_reference.clone(),
+        line: None,
+        bank_amount: None,
+        payment: Some(payment),
+    }).collect();
+
+    if !report.missing_in_system.is_empty() || !report.mismatched.is_empty() {
+        log::warn!("Settlement report {start}..{end}: {} payment(s) missing in the system, {} mismatched",
+            report.missing_in_system.len(), report.mismatched.len());
+    }
+    Ok(Json(report))
+}
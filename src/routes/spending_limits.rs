@@ -0,0 +1,132 @@
+use actix_web::{HttpRequest, get, post, web::{Data, Json}};
+use time::{Duration, UtcDateTime};
+
+use crate::{AppState, database::{crud, model::SpendingLimitRow}, error::ApiResult, model::{SpendingBudget, SpendingLimit}, return_err, routes::user_from_cookie, utils};
+
+const MAX_COOLDOWN_DAYS: u32 = 90;
+
+/// The stricter of two caps where None is no cap
+fn stricter(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The user's limits with any pending change applied once its cooldown has passed
+async fn current_limit(state: &Data<AppState>, user_id: u32) -> ApiResult<Option<SpendingLimitRow>> {
+    let Some(mut limit) = crud::get_spending_limit(&state.db, user_id).await? else {
+        return Ok(None);
+    };
+    let now = UtcDateTime::now().unix_timestamp();
+    if limit.pending_at.is_some_and(|pending_at| pending_at <= now) {
+        limit.daily = limit.pending_daily.take();
+        limit.weekly = limit.pending_weekly.take();
+        limit.cooldown_days = limit.pending_cooldown_days.take().unwrap_or(limit.cooldown_days);
+        limit.pending_at = None;
+        limit.updated_at = now;
+        crud::set_spending_limit(&state.db, &limit).await?;
+    }
+    Ok(Some(limit))
+}
+
+async fn budget_for(state: &Data<AppState>, limit: &SpendingLimitRow) -> ApiResult<SpendingBudget> {
+    let today = utils::now_local(state.env.timezone).date();
+    let week_start = today - Duration::days(today.weekday().number_days_from_monday() as i64);
+    let spent_today = crud::get_spending_since(&state.db, limit.user, utils::local_midnight(today, state.env.timezone)).await?;
+    let spent_this_week = crud::get_spending_since(&state.db, limit.user, utils::local_midnight(week_start, state.env.timezone)).await?;
+    Ok(SpendingBudget::new(limit, spent_today, spent_this_week))
+}
+
+/// What the user has left to spend today and this week, None if they have no spending limit
+pub async fn spending_budget(state: &Data<AppState>, user_id: u32) -> ApiResult<Option<SpendingBudget>> {
+    match current_limit(state, user_id).await? {
+        Some(limit) if limit.daily.is_some() || limit.weekly.is_some() => Ok(Some(budget_for(state, &limit).await?)),
+        _ => Ok(None),
+    }
+}
+
+#[get("/api/spending_limits")]
+pub async fn get_spending_limits(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<SpendingLimit>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let limit = current_limit(&state, user.id).await?.unwrap_or(SpendingLimitRow {
+        user: user.id,
+        daily: None,
+        weekly: None,
+        cooldown_days: 0,
+        pending_daily: None,
+        pending_weekly: None,
+        pending_cooldown_days: None,
+        pending_at: None,
+        updated_at: 0,
+    });
+    let budget = budget_for(&state, &limit).await?;
+    Ok(Json(SpendingLimit::new(limit, budget)))
+}
+
+#[derive(serde::Deserialize)]
+struct SpendingLimitJson {
+    daily: Option<f32>, // None removes the cap
+    weekly: Option<f32>,
+    cooldown_days: Option<u32>, // Kept as is if not given
+}
+
+/// Sets the user's own spending caps. Tightening applies right away, while raising or removing a cap
+/// and shortening the cooldown only apply after the current cooldown, to keep impulse raises from working
+#[post("/api/spending_limits")]
+pub async fn set_spending_limits(state: Data<AppState>, req: HttpRequest, body: Json<SpendingLimitJson>) -> ApiResult<Json<SpendingLimit>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let body = body.into_inner();
+    if [body.daily, body.weekly].into_iter().flatten().any(|cap| cap <= 0.0 || !cap.is_finite()) {
+        return_err!(actix_web::error::ErrorBadRequest("Spending limits must be positive"));
+    }
+    if body.cooldown_days.is_some_and(|days| days > MAX_COOLDOWN_DAYS) {
+        return_err!(actix_web::error::ErrorBadRequest("Cooldown can be at most 90 days"));
+    }
+
+    let now = UtcDateTime::now().unix_timestamp();
+    let mut limit = match current_limit(&state, user.id).await? {
+        Some(limit) => limit,
+        // Without earlier limits there is nothing to loosen
+        None => SpendingLimitRow {
+            user: user.id,
+            daily: body.daily,
+            weekly: body.weekly,
+            cooldown_days: body.cooldown_days.unwrap_or(0),
+            pending_daily: None,
+            pending_weekly: None,
+            pending_cooldown_days: None,
+            pending_at: None,
+            updated_at: now,
+        },
+    };
+    let cooldown_days = body.cooldown_days.unwrap_or(limit.cooldown_days);
+
+    let applied_daily = stricter(limit.daily, body.daily);
+    let applied_weekly = stricter(limit.weekly, body.weekly);
+    let applied_cooldown = limit.cooldown_days.max(cooldown_days);
+    let loosened = applied_daily != body.daily || applied_weekly != body.weekly || applied_cooldown != cooldown_days;
+
+    if loosened && limit.cooldown_days > 0 {
+        limit.pending_daily = body.daily;
+        limit.pending_weekly = body.weekly;
+        limit.pending_cooldown_days = Some(cooldown_days);
+        limit.pending_at = Some(now + Duration::days(limit.cooldown_days as i64).whole_seconds());
+        limit.daily = applied_daily;
+        limit.weekly = applied_weekly;
+        limit.cooldown_days = applied_cooldown;
+    } else {
+        limit.daily = body.daily;
+        limit.weekly = body.weekly;
+        limit.cooldown_days = cooldown_days;
+        limit.pending_daily = None;
+        limit.pending_weekly = None;
+        limit.pending_cooldown_days = None;
+        limit.pending_at = None;
+    }
+    limit.updated_at = now;
+    crud::set_spending_limit(&state.db, &limit).await?;
+
+    let budget = budget_for(&state, &limit).await?;
+    Ok(Json(SpendingLimit::new(limit, budget)))
+}
@@ -0,0 +1,69 @@
+use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+
+use crate::{AppState, database::{crud, model::StampCardProgramRow}, error::ApiResult, model::{ProductTarget, StampCard, StampCardProgramParams}, return_err, routes::user_from_cookie};
+
+#[derive(serde::Deserialize)]
+struct StampProgramIdJson { id: u32 }
+
+async fn assert_valid_program(state: &Data<AppState>, params: &StampCardProgramParams) -> ApiResult<()> {
+    if params.stamps_required == 0 {
+        return_err!(actix_web::error::ErrorBadRequest("Stamps required must be positive"));
+    }
+    if let ProductTarget::Product { id } = params.target {
+        crud::get_product(&state.db, id).await?;
+    }
+    Ok(())
+}
+
+/// The logged in user's progress on every active program
+#[get("/api/get_stamp_cards")]
+pub async fn get_stamp_cards(state: Data<AppState>, req: HttpRequest) -> ApiResult<Json<Vec<StampCard>>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    let programs = crud::get_stamp_programs(&state.db).await?;
+    let totals = crud::get_stamp_totals(&state.db, user.id).await?;
+
+    let cards = programs.into_iter()
+        .filter(|p| p.active)
+        .map(|program| {
+            let total = totals.iter().find(|(id, _)| *id == program.id).map_or(0, |(_, total)| *total);
+            StampCard::new(program, total)
+        })
+        .collect();
+
+    Ok(Json(cards))
+}
+
+#[get("/api/get_stamp_programs")]
+pub async fn get_stamp_programs(state: Data<AppState>) -> ApiResult<Json<Vec<StampCardProgramRow>>> {
+    let programs = crud::get_stamp_programs(&state.db).await?;
+    Ok(Json(programs))
+}
+
+#[post("/api/create_stamp_program")]
+pub async fn create_stamp_program(state: Data<AppState>, params: web::Json<StampCardProgramParams>) -> ApiResult<Json<Vec<StampCardProgramRow>>> {
+    assert_valid_program(&state, &params).await?;
+    crud::create_stamp_program(&state.db, params.into_inner().into_row(0)).await?;
+
+    let programs = crud::get_stamp_programs(&state.db).await?;
+    Ok(Json(programs))
+}
+
+#[post("/api/update_stamp_program")]
+pub async fn update_stamp_program(state: Data<AppState>, params: web::Json<StampCardProgramParams>) -> ApiResult<Json<Vec<StampCardProgramRow>>> {
+    let Some(id) = params.id else {
+        return_err!(actix_web::error::ErrorBadRequest("Missing required argument \"id\""));
+    };
+    assert_valid_program(&state, &params).await?;
+    crud::update_stamp_program(&state.db, params.into_inner().into_row(id)).await?;
+
+    let programs = crud::get_stamp_programs(&state.db).await?;
+    Ok(Json(programs))
+}
+
+#[post("/api/delete_stamp_program")]
+pub async fn delete_stamp_program(state: Data<AppState>, params: web::Json<StampProgramIdJson>) -> ApiResult<Json<Vec<StampCardProgramRow>>> {
+    crud::delete_stamp_program(&state.db, params.id).await?;
+
+    let programs = crud::get_stamp_programs(&state.db).await?;
+    Ok(Json(programs))
+}
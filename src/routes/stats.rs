@@ -1,9 +1,9 @@
 use actix_web::{get, web::{self, Data}};
 use sqlx::{Database, Encode, QueryBuilder, Type, query::{QueryAs, QueryScalar}};
 
-use crate::{AppState, error::{ApiResult, DatabaseError}};
+use crate::{AppState, error::{ApiResult, DatabaseError}, routes::payment::PaymentMethod};
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone, Copy)]
 pub struct TimeRange {
     start: Option<i64>,
     end: Option<i64>,
@@ -132,7 +132,7 @@ pub async fn purchases(state: Data<AppState>, time_range: web::Query<TimeRange>)
             COUNT(*) AS count,
             -COALESCE(SUM(amount), 0.0) AS total
         FROM StoreTransaction
-        WHERE amount <= 0 {}
+        WHERE amount <= 0 AND kind NOT IN ('bonus', 'refund', 'payout') {}
         "#, time_range.as_predicate("AND "));
     let transactions: PurchasesInfo = sqlx::query_as(&sql)
         .bind_time_range(time_range.0).fetch_one(&state.db).await
@@ -145,6 +145,15 @@ pub async fn purchases(state: Data<AppState>, time_range: web::Query<TimeRange>)
 struct DepositsInfo {
     total: f32,
     average: f32,
+    #[sqlx(skip)]
+    by_method: Vec<MethodDeposits>,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, Debug)]
+struct MethodDeposits {
+    method: Option<PaymentMethod>, // None for admin adjustments and other deposits made outside a provider
+    count: u32,
+    total: f32,
 }
 
 #[get("/api/stats/deposits")]
@@ -154,12 +163,26 @@ pub async fn deposits(state: Data<AppState>, time_range: web::Query<TimeRange>)
             COALESCE(SUM(st.amount), 0.0) AS total,
             COALESCE(AVG(st.amount), 0.0) AS average
         FROM StoreTransaction st
-        WHERE st.amount > 0 {}
+        WHERE st.amount > 0 AND st.kind NOT IN ('bonus', 'voucher', 'refund', 'payout') {}
         "#, time_range.as_predicate("AND "));
-    let info: DepositsInfo = sqlx::query_as(&sql)
+    let mut info: DepositsInfo = sqlx::query_as(&sql)
         .bind_time_range(time_range.0).fetch_one(&state.db).await
         .map_err(DatabaseError::from)?;
 
+    let sql = format!(r#"
+        SELECT
+            st.payment_method AS method,
+            COUNT(*) AS count,
+            COALESCE(SUM(st.amount), 0.0) AS total
+        FROM StoreTransaction st
+        WHERE st.amount > 0 AND st.kind NOT IN ('bonus', 'voucher', 'refund', 'payout') {}
+        GROUP BY st.payment_method
+        ORDER BY total DESC
+        "#, time_range.as_predicate("AND "));
+    info.by_method = sqlx::query_as(&sql)
+        .bind_time_range(time_range.0).fetch_all(&state.db).await
+        .map_err(DatabaseError::from)?;
+
     Ok(web::Json(info))
 }
 
@@ -1,8 +1,7 @@
 use actix_web::{HttpRequest, Result, get, post, web::{self, Data}};
 use serde::{Deserialize, Serialize};
-use sqlx::database;
 
-use crate::{AppState, Role, database::{crud, model::UserRow}, error::ApiResult, model::{PendingTransaction, UserResponse}, return_err, routes::user_from_cookie};
+use crate::{AppState, Role, database::{crud, model::UserRow}, error::ApiResult, model::{NotificationKind, PendingTransaction, TransactionKind, UserResponse}, return_err, routes::{spending_limits, user_from_cookie}};
 
 #[get("/api/get_user")]
 pub async fn get_user(state: Data<AppState>, req: HttpRequest) -> Result<web::Json<UserResponse>, actix_web::Error> {
@@ -15,7 +14,13 @@ pub async fn get_user(state: Data<AppState>, req: HttpRequest) -> Result<web::Js
         balance: user.balance,
         role: user.role,
         on_leaderboard: user.on_leaderboard,
-        private_transactions: user.private_transactions
+        private_transactions: user.private_transactions,
+        is_member: user.is_member,
+        low_balance_alerts: user.low_balance_alerts,
+        low_balance_threshold: user.low_balance_threshold,
+        email_receipts: user.email_receipts,
+        monthly_statements: user.monthly_statements,
+        spending_budget: spending_limits::spending_budget(&state, user.id).await?,
     };
     Ok(web::Json(user_response))
 }
@@ -41,8 +46,8 @@ pub async fn get_users(state: Data<AppState>, req: HttpRequest, query: web::Quer
 
     match &query.role {
         Some(role) => {
-            let users = crud::get_users_from_role(&state.db, Role::from_str(role.as_str())).await?;
-            Ok(web::Json(GetUsersResponse { users: users }))
+            let users = crud::get_users_from_role(&state.db, Role::from(role.as_str())).await?;
+            Ok(web::Json(GetUsersResponse { users }))
         },
         None => { return_err!(actix_web::error::ErrorBadRequest("")); }
     }
@@ -60,6 +65,15 @@ pub async fn delete_user(state: Data<AppState>, req: HttpRequest, query: web::Qu
     if user.role <= Role::Maintainer {
         return_err!(actix_web::error::ErrorForbidden("Cannot delete other users"));
     }
+
+    // Deleting a user with money left would lose track of it, pay it out first
+    let target = crud::get_user(&state.db, Some(query.id), None).await?;
+    if target.balance.abs() >= 0.005 {
+        return_err!(actix_web::error::ErrorConflict("User has a balance left, pay it out or settle it before deleting"));
+    }
+    if crud::get_open_payout(&state.db, target.id).await?.is_some() {
+        return_err!(actix_web::error::ErrorConflict("User has a payout in progress"));
+    }
     
     crud::delete_user(&state.db, query.id).await?;
 
@@ -72,7 +86,8 @@ struct UpdateUserParams {
     name: Option<String>,
     balance: Option<f32>,
     role: Option<Role>,
-
+    is_member: Option<bool>,
+    note: Option<String>, // Reason for a balance change, shown to the user
 }
 
 #[derive(Deserialize)]
@@ -91,7 +106,11 @@ pub async fn set_username(state: Data<AppState>, req: HttpRequest, params: web::
 #[derive(Deserialize)]
 struct UserFlagsQueryParams {
     private_transactions: Option<bool>,
-    on_leaderboard: Option<bool>
+    on_leaderboard: Option<bool>,
+    low_balance_alerts: Option<bool>,
+    low_balance_threshold: Option<f32>,
+    email_receipts: Option<bool>,
+    monthly_statements: Option<bool>,
 }
 
 #[post("/api/set_user_flags")]
@@ -104,6 +123,22 @@ pub async fn set_user_flags(state: Data<AppState>, req: HttpRequest, flags: web:
     if let Some(on_leaderboard) = flags.on_leaderboard {
         crud::set_on_leaderboard(&state.db, user.id, on_leaderboard).await?;
     }
+    if flags.low_balance_threshold.is_some_and(|threshold| threshold < 0.0 || !threshold.is_finite()) {
+        return_err!(actix_web::error::ErrorBadRequest("low_balance_threshold must not be negative"));
+    }
+    crud::set_email_preferences(&state.db, user.id, flags.low_balance_alerts, flags.low_balance_threshold, flags.email_receipts, flags.monthly_statements).await?;
+
+    Ok(())
+}
+
+/// Sends an email to the caller, to check the SMTP settings
+#[post("/api/email/test")]
+pub async fn send_test_email(state: Data<AppState>, req: HttpRequest) -> ApiResult<()> {
+    let user = user_from_cookie(&state.db, &req).await?;
+    if !state.mailer.is_enabled() {
+        return_err!(actix_web::error::ErrorServiceUnavailable("Email is not configured, set SMTP_HOST"));
+    }
+    state.mailer.send(&user.email, "Konsfekt test email", String::from("Email from Konsfekt works.")).await?;
 
     Ok(())
 }
@@ -120,15 +155,19 @@ pub async fn update_user(state: Data<AppState>, req: HttpRequest, params: web::J
         return_err!(actix_web::error::ErrorForbidden("Cannot change an admins information"));
     }
     if let Some(role) = params.role { user.role = role };
+    if let Some(is_member) = params.is_member { user.is_member = is_member };
     if let Some(balance) = params.balance { 
         if balance != user.balance {
-            let transaction = PendingTransaction {
-                user: Some(user.id), 
-                amount: balance - user.balance, 
-                products: Vec::new(),
-                admin_issued: true
-            };
-            crud::create_transaction(&state.db, transaction).await?;
+            let change = balance - user.balance;
+            let mut transaction = PendingTransaction::balance_change(user.id, change, TransactionKind::Adjustment);
+            transaction.note = params.note.clone();
+            let transaction_id = crud::create_transaction(&state.db, transaction).await?;
+
+            let mut message = format!("An admin adjusted your balance by {change:+.2} kr");
+            if let Some(note) = &params.note {
+                message += &format!(": {note}");
+            }
+            crud::create_notification(&state.db, user.id, NotificationKind::Adjustment, &message, Some(transaction_id)).await?;
         }
         user.balance = balance 
     };
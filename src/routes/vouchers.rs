@@ -0,0 +1,131 @@
+use actix_web::{HttpRequest, get, post, web::{self, Data, Json}};
+use time::UtcDateTime;
+use uuid::Uuid;
+
+use crate::{AppState, database::{crud, model::VoucherRow}, error::ApiResult, model::{PendingTransaction, TransactionKind, Voucher, VoucherParams}, return_err, routes::user_from_cookie};
+
+const MAX_VOUCHERS_PER_REQUEST: u32 = 500;
+
+#[derive(serde::Deserialize)]
+struct VoucherIdJson { id: u32 }
+
+#[derive(serde::Deserialize)]
+struct VoucherCodeJson { code: String }
+
+#[derive(serde::Serialize)]
+struct RedeemVoucherResponse {
+    value: f32,
+    balance: f32,
+}
+
+/// Codes are case insensitive and stored in upper case
+fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+/// E.g. `3F9A-1B2C-77D0`
+fn generate_code() -> String {
+    let hex = Uuid::new_v4().simple().to_string().to_uppercase();
+    format!("{}-{}-{}", &hex[0..4], &hex[4..8], &hex[8..12])
+}
+
+fn assert_valid_params(params: &VoucherParams) -> ApiResult<()> {
+    if params.value <= 0.0 {
+        return_err!(actix_web::error::ErrorBadRequest("Voucher value must be positive"));
+    }
+    if params.max_redemptions == 0 {
+        return_err!(actix_web::error::ErrorBadRequest("Voucher must be redeemable at least once"));
+    }
+    if params.count == 0 || params.count > MAX_VOUCHERS_PER_REQUEST {
+        return_err!(actix_web::error::ErrorBadRequest("Invalid number of vouchers"));
+    }
+    if params.expires_at.is_some_and(|expires_at| expires_at <= UtcDateTime::now().unix_timestamp()) {
+        return_err!(actix_web::error::ErrorBadRequest("Voucher expires in the past"));
+    }
+    if let Some(code) = &params.code {
+        if params.count != 1 {
+            return_err!(actix_web::error::ErrorBadRequest("A custom code can only be used for a single voucher"));
+        }
+        let code = normalize_code(code);
+        if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return_err!(actix_web::error::ErrorBadRequest("Voucher codes may only contain letters, digits and dashes"));
+        }
+    }
+    Ok(())
+}
+
+#[get("/api/get_vouchers")]
+pub async fn get_vouchers(state: Data<AppState>) -> ApiResult<Json<Vec<Voucher>>> {
+    let vouchers = crud::get_vouchers(&state.db).await?;
+    let redemptions = crud::get_voucher_redemptions(&state.db).await?;
+    Ok(Json(Voucher::from_rows(vouchers, redemptions)))
+}
+
+/// Returns the created vouchers
+#[post("/api/create_vouchers")]
+pub async fn create_vouchers(state: Data<AppState>, req: HttpRequest, params: web::Json<VoucherParams>) -> ApiResult<Json<Vec<VoucherRow>>> {
+    let admin = user_from_cookie(&state.db, &req).await?;
+    assert_valid_params(&params)?;
+
+    let params = params.into_inner();
+    let mut created = Vec::new();
+    for _ in 0..params.count {
+        let code = params.code.as_deref().map(normalize_code).unwrap_or_else(generate_code);
+        if crud::get_voucher_by_code(&state.db, &code).await?.is_some() {
+            return_err!(actix_web::error::ErrorConflict("Voucher code already exists"));
+        }
+        let mut voucher = VoucherRow {
+            id: 0,
+            code,
+            value: params.value,
+            max_redemptions: params.max_redemptions,
+            expires_at: params.expires_at,
+            note: params.note.clone(),
+            created_by: Some(admin.id),
+            created_at: UtcDateTime::now().unix_timestamp(),
+        };
+        voucher.id = crud::create_voucher(&state.db, voucher.clone()).await?;
+        created.push(voucher);
+    }
+    log::info!("User {} created {} voucher(s) worth {} kr", admin.id, created.len(), params.value);
+
+    Ok(Json(created))
+}
+
+#[post("/api/delete_voucher")]
+pub async fn delete_voucher(state: Data<AppState>, params: web::Json<VoucherIdJson>) -> ApiResult<Json<Vec<Voucher>>> {
+    crud::delete_voucher(&state.db, params.id).await?;
+
+    let vouchers = crud::get_vouchers(&state.db).await?;
+    let redemptions = crud::get_voucher_redemptions(&state.db).await?;
+    Ok(Json(Voucher::from_rows(vouchers, redemptions)))
+}
+
+#[post("/api/redeem_voucher")]
+pub async fn redeem_voucher(state: Data<AppState>, req: HttpRequest, params: web::Json<VoucherCodeJson>) -> ApiResult<Json<RedeemVoucherResponse>> {
+    let user = user_from_cookie(&state.db, &req).await?;
+
+    let Some(voucher) = crud::get_voucher_by_code(&state.db, &normalize_code(&params.code)).await? else {
+        return_err!(actix_web::error::ErrorNotFound("Invalid voucher code"));
+    };
+    if voucher.expires_at.is_some_and(|expires_at| expires_at <= UtcDateTime::now().unix_timestamp()) {
+        return_err!(actix_web::error::ErrorGone("Voucher has expired"));
+    }
+    if crud::has_redeemed_voucher(&state.db, voucher.id, user.id).await? {
+        return_err!(actix_web::error::ErrorConflict("Voucher already redeemed"));
+    }
+    let Some(redemption_id) = crud::claim_voucher(&state.db, voucher.id, user.id).await? else {
+        return_err!(actix_web::error::ErrorGone("Voucher has been used up"));
+    };
+
+    let mut transaction = PendingTransaction::balance_change(user.id, voucher.value, TransactionKind::Voucher);
+    transaction.note = Some(voucher.code);
+    let transaction_id = crud::create_transaction(&state.db, transaction).await?;
+    crud::set_redemption_transaction(&state.db, redemption_id, transaction_id).await?;
+
+    let balance = user.balance + voucher.value;
+    crud::update_user_balance(&state.db, user.id, balance).await?;
+    log::info!("User {} redeemed voucher {} for {} kr", user.id, voucher.id, voucher.value);
+
+    Ok(Json(RedeemVoucherResponse { value: voucher.value, balance }))
+}
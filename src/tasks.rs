@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+
+use crate::{EnvironmentVariables, database::crud, email::{self, Mailer}, error::DatabaseError, routes::payment::swish, utils};
+
+const PRODUCT_FLAG_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SWISH_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Pending requests older than this are polled, in case their callback was lost
+const STALE_PAYMENT_AGE: time::Duration = time::Duration::minutes(2);
+/// Pending requests older than this are given up on
+const ABANDONED_PAYMENT_AGE: time::Duration = time::Duration::hours(1);
+const STATEMENT_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Spawns a task refreshing the automatically computed product flags every
+/// [`PRODUCT_FLAG_REFRESH_INTERVAL`], starting immediately
+pub fn spawn_product_flag_refresh(pool: SqlitePool, env: EnvironmentVariables) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRODUCT_FLAG_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = refresh_product_flags(&pool, &env).await {
+                log::error!("Could not refresh product flags: {err}");
+            }
+        }
+    });
+}
+
+/// Expires old `new_product` flags and recomputes `popular` from recent sales
+pub async fn refresh_product_flags(pool: &SqlitePool, env: &EnvironmentVariables) -> Result<(), DatabaseError> {
+    let now = OffsetDateTime::now_utc();
+
+    let expired = crud::expire_new_products(pool, (now - time::Duration::days(env.new_product_days)).unix_timestamp()).await?;
+    if expired > 0 {
+        log::info!("{expired} product(s) are no longer flagged as new");
+    }
+
+    let since = (now - time::Duration::days(env.popular_window_days)).unix_timestamp();
+    crud::update_popular_products(pool, since, env.popular_product_count).await?;
+
+    Ok(())
+}
+
+/// Spawns a task polling Swish for the status of stale pending payment requests every [`SWISH_POLL_INTERVAL`]
+pub fn spawn_swish_polling(pool: SqlitePool, client: Client) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWISH_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = poll_stale_payments(&pool, &client).await {
+                log::error!("Could not poll pending Swish payments: {err}");
+            }
+        }
+    });
+}
+
+/// Asks Swish about every request pending for longer than [`STALE_PAYMENT_AGE`]. Requests older
+/// than [`ABANDONED_PAYMENT_AGE`] that Swish still says are pending, or does not know of, are cancelled.
+/// Requests Swish cannot be asked about stay pending and are retried on the next poll
+pub async fn poll_stale_payments(pool: &SqlitePool, client: &Client) -> Result<(), DatabaseError> {
+    let now = OffsetDateTime::now_utc();
+    let abandoned_before = (now - ABANDONED_PAYMENT_AGE).unix_timestamp();
+
+    let stale = crud::get_stale_payment_requests(pool, (now - STALE_PAYMENT_AGE).unix_timestamp()).await?;
+    for payment_request in stale {
+        let abandon = payment_request.created_at < abandoned_before;
+        if let Err(err) = swish::poll_payment_request(pool, client, &payment_request, abandon).await {
+            log::warn!("Could not poll Swish payment {}: {err}", payment_request.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawns a task emailing last month's statement to opted in users, checking every [`STATEMENT_CHECK_INTERVAL`]
+pub fn spawn_monthly_statements(pool: SqlitePool, env: EnvironmentVariables, mailer: Mailer) {
+    if !mailer.is_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(STATEMENT_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = send_monthly_statements(&pool, &env, &mailer).await {
+                log::error!("Could not send monthly statements: {err}");
+            }
+        }
+    });
+}
+
+/// Sends the statement for the previous month in the store's time zone to every opted in user not yet sent one
+pub async fn send_monthly_statements(pool: &SqlitePool, env: &EnvironmentVariables, mailer: &Mailer) -> Result<(), DatabaseError> {
+    let this_month = utils::now_local(env.timezone).date().replace_day(1).unwrap();
+    let last_month = (this_month - time::Duration::days(1)).replace_day(1).unwrap();
+    let month = format!("{}-{:02}", last_month.year(), last_month.month() as u8);
+    let start = utils::local_midnight(last_month, env.timezone);
+    let end = utils::local_midnight(this_month, env.timezone);
+
+    for user in crud::get_statement_recipients(pool, &month).await? {
+        let transactions = crud::get_user_transactions_between(pool, user.id, start, end).await?;
+        let (subject, body) = email::monthly_statement(last_month, &transactions, user.balance, user.private_transactions, env.timezone);
+        match mailer.send(&user.email, &subject, body).await {
+            Ok(()) => crud::mark_statement_sent(pool, user.id, &month).await?,
+            // Retried on the next check
+            Err(err) => log::warn!("Could not send {month} statement to user {}: {err}", user.id),
+        }
+    }
+
+    Ok(())
+}
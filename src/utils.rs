@@ -1,9 +1,11 @@
-use std::{fs, io::BufReader};
+use std::{fs, io::{BufReader, Cursor}};
 
 use actix_multipart::form::tempfile::TempFile;
 use actix_web::web::Data;
-use image::ImageReader;
-use time::OffsetDateTime;
+use image::{ImageReader, Luma};
+use qrcode::{QrCode, render::svg};
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt, Tz};
 
 use crate::{AppState, error::GenericError};
 
@@ -17,7 +19,7 @@ pub fn get_path(state: &Data<AppState>, path: &str) -> String {
 }
 
 pub fn read_to_string(path: &str) -> Result<String, std::io::Error> {
-    return fs::read_to_string(path)
+    fs::read_to_string(path)
 }
 
 pub const IMG_DISK_SIZE: u32 = 512;
@@ -44,6 +46,37 @@ pub fn save_img_to_disk(img_file: TempFile, name: &str) -> Option<()> {
     resized.save(format!("{IMG_DISK_PATH}{}.webp", name)).ok()
 }
 
+#[derive(serde::Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// Encodes `data` as a QR code no larger than `size` x `size` pixels
+pub fn render_qr(data: &str, format: QrFormat, size: u32) -> Result<Vec<u8>, GenericError> {
+    let code = QrCode::new(data.as_bytes()).map_err(|_| GenericError::new("Data does not fit in a QR code"))?;
+    match format {
+        QrFormat::Png => {
+            let image = code.render::<Luma<u8>>().max_dimensions(size, size).build();
+            let mut png = Cursor::new(Vec::new());
+            image.write_to(&mut png, image::ImageFormat::Png).map_err(|_| GenericError::new("Failed to encode QR code"))?;
+            Ok(png.into_inner())
+        },
+        QrFormat::Svg => Ok(code.render::<svg::Color>().max_dimensions(size, size).build().into_bytes()),
+    }
+}
+
 pub fn delete_img_from_disk(name: &str) -> Result<(), GenericError> {
     match fs::remove_file(format!("{IMG_DISK_PATH}{name}.webp")) {
         Ok(_) => Ok(()),
@@ -55,5 +88,15 @@ pub fn delete_img_from_disk(name: &str) -> Result<(), GenericError> {
 ///
 /// Falls back to [`OffsetDateTime::UNIX_EPOCH`] if `unix` is outside the valid range.
 pub fn datetime_from_timestamp(unix: i64) -> OffsetDateTime {
-    OffsetDateTime::from_unix_timestamp(unix).unwrap_or_else(|_| OffsetDateTime::UNIX_EPOCH)
+    OffsetDateTime::from_unix_timestamp(unix).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+/// Current time in the store's local time zone
+pub fn now_local(timezone: &Tz) -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_timezone(timezone)
+}
+
+/// Unix timestamp of the start of `date` in the store's local time zone
+pub fn local_midnight(date: Date, timezone: &Tz) -> i64 {
+    PrimitiveDateTime::new(date, Time::MIDNIGHT).assume_timezone(timezone).unwrap_first().unix_timestamp()
 }